@@ -1,84 +1,472 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process;
 
 struct Number {
     value: u32,
-    symbol: (usize, usize, char),
+    x: usize,
+    y: usize,
+    width: usize,
+    symbol: Option<(usize, usize, char)>,
 }
 
-#[derive(Hash, PartialEq, Eq)]
+impl Number {
+    fn is_part(&self) -> bool {
+        self.symbol.is_some()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 struct Pos(usize, usize);
 
-fn parse_input(input: &str) -> Vec<Number> {
-    let input: Vec<Vec<char>> = input
+// Each row with its 1-based line number in the input, blank lines skipped.
+fn parse_grid_lines(input: &str) -> Vec<(usize, Vec<char>)> {
+    input
         .lines()
-        .map(|line| line.trim())
-        .filter(|&line| !line.is_empty())
-        .map(|line| line.chars().collect())
-        .collect();
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|&(_, line)| !line.is_empty())
+        .map(|(line_no, line)| (line_no, line.chars().collect()))
+        .collect()
+}
+
+fn parse_grid(input: &str) -> Vec<Vec<char>> {
+    parse_grid_lines(input)
+        .into_iter()
+        .map(|(_, row)| row)
+        .collect()
+}
+
+fn parse_input(input: &str) -> Vec<Number> {
+    find_numbers(&parse_grid(input))
+}
+
+#[derive(Debug, PartialEq)]
+enum SchematicError {
+    Ragged {
+        line: usize,
+        width: usize,
+        expected: usize,
+        first_line: usize,
+    },
+}
+
+impl fmt::Display for SchematicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchematicError::Ragged {
+                line,
+                width,
+                expected,
+                first_line,
+            } => write!(
+                f,
+                "line {} is {} cells wide, expected {} like line {}",
+                line, width, expected, first_line
+            ),
+        }
+    }
+}
+
+// Ragged rows are fine for the solvers, which treat missing cells as blank,
+// but they usually mean the schematic was copied badly.
+fn check_rectangular(grid: &[(usize, Vec<char>)]) -> Result<(), SchematicError> {
+    let Some((first_line, first)) = grid.first() else {
+        return Ok(());
+    };
+
+    match grid.iter().find(|(_, row)| row.len() != first.len()) {
+        Some((line, row)) => Err(SchematicError::Ragged {
+            line: *line,
+            width: row.len(),
+            expected: first.len(),
+            first_line: *first_line,
+        }),
+        None => Ok(()),
+    }
+}
 
+fn parse_input_strict(input: &str) -> Result<Vec<Number>, SchematicError> {
+    let grid = parse_grid_lines(input);
+    check_rectangular(&grid)?;
+    let grid: Vec<Vec<char>> = grid.into_iter().map(|(_, row)| row).collect();
+    Ok(find_numbers(&grid))
+}
+
+fn find_numbers(input: &[Vec<char>]) -> Vec<Number> {
     let mut numbers = Vec::new();
 
     for (y, row) in input.iter().enumerate() {
-        let mut checked = 0;
+        let above = y.checked_sub(1).map(|i| input[i].as_slice());
+        let below = input.get(y + 1).map(Vec::as_slice);
+        find_row_numbers(above, row, below, y, &mut |number| numbers.push(number));
+    }
 
-        for (x, &c) in row.iter().enumerate() {
-            if c.is_digit(10) && x >= checked {
-                let mut w = 1;
+    numbers
+}
 
-                while x + w < row.len() && row[x + w].is_ascii_digit() {
-                    w += 1;
-                }
+// Only the rows directly above and below can hold a symbol adjacent to a
+// number, so this is all the context either parser needs for a row.
+fn find_row_numbers(
+    above: Option<&[char]>,
+    row: &[char],
+    below: Option<&[char]>,
+    y: usize,
+    emit: &mut impl FnMut(Number),
+) {
+    let mut checked = 0;
 
-                checked = x + w;
+    for (x, &c) in row.iter().enumerate() {
+        if c.is_ascii_digit() && x >= checked {
+            let mut w = 1;
 
-                let value: u32 = row[x..x + w].iter().collect::<String>().parse().unwrap();
+            while x + w < row.len() && row[x + w].is_ascii_digit() {
+                w += 1;
+            }
 
-                let mut symbol: Option<(usize, usize, char)> = None;
-                'outer: for i in y.saturating_sub(1)..(y + 2).min(input.len()) {
-                    for j in x.saturating_sub(1)..(x + w + 1).min(row.len()) {
-                        let c = input[i][j];
-                        if !c.is_ascii_digit() && c != '.' {
-                            symbol = Some((j, i, c));
-                            break 'outer;
-                        }
-                    }
-                }
+            checked = x + w;
+
+            let value: u32 = row[x..x + w].iter().collect::<String>().parse().unwrap();
 
-                if let Some(symbol) = symbol {
-                    numbers.push(Number { value, symbol });
+            let neighbours = [
+                above.map(|line| (y - 1, line)),
+                Some((y, row)),
+                below.map(|line| (y + 1, line)),
+            ];
+
+            let mut symbol: Option<(usize, usize, char)> = None;
+            'outer: for (i, line) in neighbours.into_iter().flatten() {
+                for (j, &c) in line
+                    .iter()
+                    .enumerate()
+                    .take(x + w + 1)
+                    .skip(x.saturating_sub(1))
+                {
+                    if !c.is_ascii_digit() && c != '.' {
+                        symbol = Some((j, i, c));
+                        break 'outer;
+                    }
                 }
             }
+
+            emit(Number {
+                value,
+                x,
+                y,
+                width: w,
+                symbol,
+            });
         }
     }
+}
 
-    numbers
+/// Scans a schematic line by line, keeping only the previous, current and
+/// next rows in memory. Yields the same numbers, in the same order, as
+/// `find_numbers` does for the whole grid.
+struct NumberStream<I> {
+    lines: I,
+    prev: Option<Vec<char>>,
+    cur: Option<Vec<char>>,
+    next: Option<Vec<char>>,
+    y: Option<usize>,
+    pending: VecDeque<Number>,
 }
 
-fn find_part_numbers(input: &Vec<Number>) -> u32 {
-    input.iter().fold(0, |acc, cur| acc + cur.value)
+impl<I, S> NumberStream<I>
+where
+    I: Iterator<Item = S>,
+    S: AsRef<str>,
+{
+    fn new(lines: I) -> Self {
+        NumberStream {
+            lines,
+            prev: None,
+            cur: None,
+            next: None,
+            y: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn next_row(&mut self) -> Option<Vec<char>> {
+        self.lines.by_ref().find_map(|line| {
+            let line = line.as_ref().trim();
+            (!line.is_empty()).then(|| line.chars().collect())
+        })
+    }
 }
 
-fn find_gear_ratio(input: &Vec<Number>) -> u32 {
+impl<I, S> Iterator for NumberStream<I>
+where
+    I: Iterator<Item = S>,
+    S: AsRef<str>,
+{
+    type Item = Number;
+
+    fn next(&mut self) -> Option<Number> {
+        loop {
+            if let Some(number) = self.pending.pop_front() {
+                return Some(number);
+            }
+
+            let y = match self.y {
+                None => {
+                    self.cur = self.next_row();
+                    self.next = self.next_row();
+                    0
+                }
+                Some(y) => {
+                    self.prev = self.cur.take();
+                    self.cur = self.next.take();
+                    self.next = self.next_row();
+                    y + 1
+                }
+            };
+            self.y = Some(y);
+
+            let row = self.cur.as_deref()?;
+            let pending = &mut self.pending;
+            find_row_numbers(
+                self.prev.as_deref(),
+                row,
+                self.next.as_deref(),
+                y,
+                &mut |number| pending.push_back(number),
+            );
+        }
+    }
+}
+
+fn find_part_numbers(input: &[Number]) -> u64 {
+    input
+        .iter()
+        .filter(|&number| number.is_part())
+        .fold(0, |acc, cur| acc + cur.value as u64)
+}
+
+fn find_gears(input: &[Number]) -> HashMap<Pos, Vec<u32>> {
     let mut possible_gears = HashMap::new();
 
     input.iter().for_each(|part| {
-        let (x, y, _) = part.symbol;
-        possible_gears
-            .entry(Pos(x, y))
-            .or_insert(Vec::new())
-            .push(part.value);
+        if let Some((x, y, _)) = part.symbol {
+            possible_gears
+                .entry(Pos(x, y))
+                .or_insert(Vec::new())
+                .push(part.value);
+        }
     });
 
+    possible_gears.retain(|_, parts| parts.len() == 2);
     possible_gears
+}
+
+fn find_gear_ratio(input: &[Number]) -> u64 {
+    find_gears(input)
         .values()
-        .filter(|&parts| parts.len() == 2)
-        .map(|parts| parts.iter().product::<u32>())
+        .map(|parts| parts[0] as u64 * parts[1] as u64)
         .sum()
 }
 
+// Numbers arrive in row order and can only touch a symbol at most one row
+// below them, so once the stream reaches row `y` every candidate above row
+// `y - 1` has seen all of its parts and can be reported and dropped.
+fn stream_gears(numbers: impl Iterator<Item = Number>, mut emit: impl FnMut(Pos, Vec<u32>)) {
+    let mut candidates: HashMap<Pos, Vec<u32>> = HashMap::new();
+    let mut row = 0;
+
+    for number in numbers {
+        if number.y != row {
+            row = number.y;
+            candidates.retain(|&pos, parts| {
+                if pos.1 + 1 >= row {
+                    return true;
+                }
+                if parts.len() == 2 {
+                    emit(pos, std::mem::take(parts));
+                }
+                false
+            });
+        }
+
+        if let Some((x, y, _)) = number.symbol {
+            candidates.entry(Pos(x, y)).or_default().push(number.value);
+        }
+    }
+
+    for (pos, parts) in candidates {
+        if parts.len() == 2 {
+            emit(pos, parts);
+        }
+    }
+}
+
+// Totals are u64 so that schematics millions of rows long don't overflow.
+fn stream_totals<S: AsRef<str>>(lines: impl Iterator<Item = S>) -> (u64, u64) {
+    let mut part_total = 0;
+    let mut gear_total = 0;
+
+    let numbers = NumberStream::new(lines).inspect(|number| {
+        if number.is_part() {
+            part_total += number.value as u64;
+        }
+    });
+    stream_gears(numbers, |_, parts| {
+        gear_total += parts[0] as u64 * parts[1] as u64
+    });
+
+    (part_total, gear_total)
+}
+
+const RESET: &str = "\x1b[0m";
+const PART_STYLE: &str = "\x1b[1;32m";
+const NON_PART_STYLE: &str = "\x1b[2m";
+const GEAR_STYLE: &str = "\x1b[1;33m";
+const SYMBOL_STYLE: &str = "\x1b[31m";
+
+/// A square region of the schematic centred on `(x, y)`.
+struct Window {
+    x: usize,
+    y: usize,
+    radius: usize,
+}
+
+// Colours each cell by what it means for the puzzle, so adjacency mistakes
+// stand out: part numbers are bright, numbers with no symbol nearby are
+// dimmed, and gears are highlighted and listed with their ratio underneath.
+fn render(grid: &[Vec<char>], numbers: &[Number], window: Option<&Window>) -> String {
+    let mut styles: Vec<Vec<&str>> = grid.iter().map(|row| vec![""; row.len()]).collect();
+
+    for (y, row) in grid.iter().enumerate() {
+        for (x, &c) in row.iter().enumerate() {
+            if !c.is_ascii_digit() && c != '.' {
+                styles[y][x] = SYMBOL_STYLE;
+            }
+        }
+    }
+
+    for number in numbers {
+        let style = if number.is_part() {
+            PART_STYLE
+        } else {
+            NON_PART_STYLE
+        };
+        styles[number.y][number.x..number.x + number.width].fill(style);
+    }
+
+    let mut gears: Vec<(Pos, Vec<u32>)> = find_gears(numbers)
+        .into_iter()
+        .filter(|(Pos(x, y), _)| grid[*y][*x] == '*')
+        .collect();
+    gears.sort_by_key(|&(Pos(x, y), _)| (y, x));
+
+    for (Pos(x, y), _) in &gears {
+        styles[*y][*x] = GEAR_STYLE;
+    }
+
+    let (rows, cols) = match window {
+        Some(w) => (
+            w.y.saturating_sub(w.radius)..w.y + w.radius + 1,
+            w.x.saturating_sub(w.radius)..w.x + w.radius + 1,
+        ),
+        None => (0..grid.len(), 0..usize::MAX),
+    };
+
+    let mut output = String::new();
+
+    for (y, row) in grid.iter().enumerate() {
+        if !rows.contains(&y) {
+            continue;
+        }
+
+        let mut current = "";
+        for (x, &c) in row.iter().enumerate() {
+            if !cols.contains(&x) {
+                continue;
+            }
+
+            let style = styles[y][x];
+            if style != current {
+                output.push_str(RESET);
+                output.push_str(style);
+                current = style;
+            }
+            output.push(c);
+        }
+        output.push_str(RESET);
+        output.push('\n');
+    }
+
+    for (Pos(x, y), parts) in &gears {
+        if rows.contains(y) && cols.contains(x) {
+            output.push_str(&format!(
+                "{}gear{} ({}, {}): {} * {} = {}\n",
+                GEAR_STYLE,
+                RESET,
+                x,
+                y,
+                parts[0],
+                parts[1],
+                parts[0] * parts[1]
+            ));
+        }
+    }
+
+    output
+}
+
+fn parse_window(args: &[String]) -> Option<Window> {
+    match args {
+        [] => None,
+        [x, y] => Some(Window {
+            x: x.parse().expect("x should be a number"),
+            y: y.parse().expect("y should be a number"),
+            radius: 5,
+        }),
+        [x, y, radius] => Some(Window {
+            x: x.parse().expect("x should be a number"),
+            y: y.parse().expect("y should be a number"),
+            radius: radius.parse().expect("radius should be a number"),
+        }),
+        _ => panic!("usage: render [x y [radius]]"),
+    }
+}
+
 fn main() {
     let input = include_str!("../input.txt");
-    let input = parse_input(&input);
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let input = match args.first().map(String::as_str) {
+        Some("render") => {
+            let grid = parse_grid(input);
+            let numbers = find_numbers(&grid);
+            let window = parse_window(&args[1..]);
+            print!("{}", render(&grid, &numbers, window.as_ref()));
+            return;
+        }
+        Some("stream") => {
+            let (part_1_total, part_2_total) = match args.get(1) {
+                Some(path) => {
+                    let file = File::open(path).expect("schematic file should be readable");
+                    stream_totals(BufReader::new(file).lines().map(Result::unwrap))
+                }
+                None => stream_totals(input.lines()),
+            };
+            println!("Part 1: {}", part_1_total);
+            println!("Part 2: {}", part_2_total);
+            return;
+        }
+        Some("strict") => match parse_input_strict(input) {
+            Ok(numbers) => numbers,
+            Err(err) => {
+                eprintln!("Invalid schematic: {}", err);
+                process::exit(1);
+            }
+        },
+        _ => parse_input(input),
+    };
 
     let part_1_total = find_part_numbers(&input);
     println!("Part 1: {}", part_1_total);
@@ -117,4 +505,115 @@ mod tests {
         let total = find_gear_ratio(&input);
         assert_eq!(total, 467835);
     }
+
+    #[test]
+    fn test_render() {
+        let grid = parse_grid(SAMPLE_INPUT);
+        let numbers = find_numbers(&grid);
+        let output = render(&grid, &numbers, None);
+
+        assert!(output.contains(&format!("{}467", PART_STYLE)));
+        assert!(output.contains(&format!("{}114", NON_PART_STYLE)));
+        assert!(output.contains("(3, 1): 467 * 35 = 16345"));
+        assert!(output.contains("(5, 8): 755 * 598 = 451490"));
+    }
+
+    #[test]
+    fn test_render_window() {
+        let grid = parse_grid(SAMPLE_INPUT);
+        let numbers = find_numbers(&grid);
+        let window = Window {
+            x: 1,
+            y: 1,
+            radius: 1,
+        };
+        let output = render(&grid, &numbers, Some(&window));
+        let plain: Vec<String> = output
+            .lines()
+            .map(|line| {
+                line.replace(RESET, "")
+                    .replace(PART_STYLE, "")
+                    .replace(SYMBOL_STYLE, "")
+            })
+            .collect();
+
+        assert_eq!(plain[..3], ["467", "...", "..3"]);
+        assert!(!output.contains("451490"));
+    }
+
+    #[test]
+    fn test_stream_matches_batch() {
+        let numbers = parse_input(SAMPLE_INPUT);
+        let streamed: Vec<Number> = NumberStream::new(SAMPLE_INPUT.lines()).collect();
+
+        assert_eq!(streamed.len(), numbers.len());
+        for (a, b) in streamed.iter().zip(&numbers) {
+            assert_eq!((a.value, a.x, a.y, a.symbol), (b.value, b.x, b.y, b.symbol));
+        }
+
+        assert_eq!(stream_totals(SAMPLE_INPUT.lines()), (4361, 467835));
+    }
+
+    #[test]
+    fn test_stream_real_input() {
+        let input = include_str!("../input.txt");
+        let numbers = parse_input(input);
+        let totals = (find_part_numbers(&numbers), find_gear_ratio(&numbers));
+        assert_eq!(stream_totals(input.lines()), totals);
+    }
+
+    #[test]
+    fn test_stream_totals_past_u32() {
+        // The sample's first and last rows have no symbols, so stacked copies
+        // don't touch each other.
+        let rows: Vec<&str> = SAMPLE_INPUT
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        let copies = 10_000;
+        let lines = rows.iter().cycle().take(rows.len() * copies);
+
+        let (part_total, gear_total) = stream_totals(lines);
+        assert_eq!(part_total, 4361 * copies as u64);
+        assert_eq!(gear_total, 467835 * copies as u64);
+        assert!(gear_total > u32::MAX as u64);
+    }
+
+    const RAGGED_INPUT: &str = "
+    467..114..
+    ...*
+    ..35..633.
+    ......#
+    ";
+
+    #[test]
+    fn test_ragged_rows() {
+        let input = parse_input(RAGGED_INPUT);
+        let total = find_part_numbers(&input);
+        assert_eq!(total, 467 + 35 + 633);
+
+        let streamed = stream_totals(RAGGED_INPUT.lines());
+        assert_eq!(streamed, (total, 467 * 35));
+    }
+
+    #[test]
+    fn test_strict_rejects_ragged_rows() {
+        assert!(parse_input_strict(SAMPLE_INPUT).is_ok());
+
+        let err = parse_input_strict(RAGGED_INPUT).err().unwrap();
+        assert_eq!(
+            err,
+            SchematicError::Ragged {
+                line: 3,
+                width: 4,
+                expected: 10,
+                first_line: 2
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "line 3 is 4 cells wide, expected 10 like line 2"
+        );
+    }
 }
@@ -1,19 +1,130 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::num::{NonZeroU128, NonZeroUsize};
+
+/// The numbers on one side of a card: a bitset starting at the smallest number
+/// in the input, or a hash set when the numbers are too spread out for that.
+#[derive(Debug, Clone, PartialEq)]
+enum NumberSet {
+    Bits { min: usize, words: Vec<u64> },
+    Hashed(HashSet<usize>),
+}
+
+impl NumberSet {
+    // A bitset is only worth it while it's no more words than the longest
+    // list of numbers has entries, so intersections stay linear in those.
+    fn for_range(min: usize, max: usize, widest: usize) -> Self {
+        let words = (max - min) / 64 + 1;
+        match words <= widest.max(1) {
+            true => NumberSet::Bits {
+                min,
+                words: vec![0; words],
+            },
+            false => NumberSet::Hashed(HashSet::new()),
+        }
+    }
+
+    fn insert(&mut self, num: usize) {
+        match self {
+            NumberSet::Bits { min, words } => words[(num - *min) / 64] |= 1 << ((num - *min) % 64),
+            NumberSet::Hashed(set) => {
+                set.insert(num);
+            }
+        }
+    }
+
+    fn contains(&self, num: usize) -> bool {
+        match self {
+            NumberSet::Bits { min, words } => num.checked_sub(*min).is_some_and(|i| {
+                words
+                    .get(i / 64)
+                    .is_some_and(|word| word & (1 << (i % 64)) != 0)
+            }),
+            NumberSet::Hashed(set) => set.contains(&num),
+        }
+    }
+
+    fn intersection_count(&self, other: &NumberSet) -> usize {
+        match (self, other) {
+            (NumberSet::Bits { words: a, .. }, NumberSet::Bits { words: b, .. }) => a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| (a & b).count_ones() as usize)
+                .sum(),
+            (NumberSet::Hashed(set), other) | (other, NumberSet::Hashed(set)) => {
+                set.iter().filter(|&&num| other.contains(num)).count()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Winning,
+    Revealed,
+}
+
+#[derive(Debug, PartialEq)]
+struct Duplicate {
+    side: Side,
+    number: usize,
+}
+
 struct Card {
-    winning_numbers: Vec<usize>,
-    revealed_numbers: Vec<usize>,
+    id: usize,
+    winning_numbers: NumberSet,
+    revealed_numbers: NumberSet,
+    duplicates: Vec<Duplicate>,
 }
 
 fn parse_input(input: &str) -> Vec<Card> {
-    input
+    let raw_cards: Vec<(usize, Vec<usize>, Vec<usize>)> = input
         .lines()
         .filter(|&line| !line.trim().is_empty())
         .map(|line| {
-            let (_, numbers_str) = line.split_once(":").unwrap();
-            let (winning_num_str, revealed_num_str) = numbers_str.split_once("|").unwrap();
+            let (card_str, numbers_str) = line.split_once(':').unwrap();
+            let id_str = card_str.split_whitespace().nth(1).unwrap();
+            let (winning_num_str, revealed_num_str) = numbers_str.split_once('|').unwrap();
+            (
+                id_str.parse().unwrap(),
+                parse_numbers(winning_num_str),
+                parse_numbers(revealed_num_str),
+            )
+        })
+        .collect();
+
+    // Every set shares the same shape so bitset intersections are a straight
+    // zip.
+    let numbers = || {
+        raw_cards
+            .iter()
+            .flat_map(|(_, winning, revealed)| winning.iter().chain(revealed))
+            .copied()
+    };
+    let widest = raw_cards
+        .iter()
+        .map(|(_, winning, revealed)| winning.len().max(revealed.len()))
+        .max()
+        .unwrap_or(0);
+    let empty = NumberSet::for_range(
+        numbers().min().unwrap_or(0),
+        numbers().max().unwrap_or(0),
+        widest,
+    );
+
+    raw_cards
+        .into_iter()
+        .map(|(id, winning, revealed)| {
+            let mut duplicates = Vec::new();
+            let winning_numbers = build_set(&winning, &empty, Side::Winning, &mut duplicates);
+            let revealed_numbers = build_set(&revealed, &empty, Side::Revealed, &mut duplicates);
 
             Card {
-                winning_numbers: parse_numbers(winning_num_str),
-                revealed_numbers: parse_numbers(revealed_num_str),
+                id,
+                winning_numbers,
+                revealed_numbers,
+                duplicates,
             }
         })
         .collect()
@@ -26,53 +137,501 @@ fn parse_numbers(numbers_str: &str) -> Vec<usize> {
         .collect()
 }
 
+fn build_set(
+    numbers: &[usize],
+    empty: &NumberSet,
+    side: Side,
+    duplicates: &mut Vec<Duplicate>,
+) -> NumberSet {
+    let mut set = empty.clone();
+
+    for &number in numbers {
+        if set.contains(number) {
+            duplicates.push(Duplicate { side, number });
+        } else {
+            set.insert(number);
+        }
+    }
+
+    set
+}
+
+#[derive(Debug, PartialEq)]
+enum CardIdError {
+    OutOfOrder { id: usize, previous: usize },
+    Missing { id: usize },
+    Repeated { id: usize },
+}
+
+impl fmt::Display for CardIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CardIdError::OutOfOrder { id, previous } => {
+                write!(f, "card {} comes after card {}", id, previous)
+            }
+            CardIdError::Missing { id } => write!(f, "card {} is missing", id),
+            CardIdError::Repeated { id } => write!(f, "card {} appears more than once", id),
+        }
+    }
+}
+
+// Copies are won by card ID, so none of these stop the cards being counted
+// except a repeated ID, which leaves it unclear which card gets the copies.
+fn validate_card_ids(cards: &[Card]) -> Vec<CardIdError> {
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    let mut previous = 0;
+
+    for card in cards {
+        if !seen.insert(card.id) {
+            errors.push(CardIdError::Repeated { id: card.id });
+        } else if card.id < previous {
+            errors.push(CardIdError::OutOfOrder {
+                id: card.id,
+                previous,
+            });
+        }
+        previous = card.id;
+    }
+
+    let last = seen.iter().copied().max().unwrap_or(0);
+    for id in 1..last {
+        if !seen.contains(&id) {
+            errors.push(CardIdError::Missing { id });
+        }
+    }
+
+    errors
+}
+
+impl fmt::Display for Duplicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let side = match self.side {
+            Side::Winning => "winning",
+            Side::Revealed => "revealed",
+        };
+        write!(
+            f,
+            "{} appears more than once in the {} numbers",
+            self.number, side
+        )
+    }
+}
+
 fn get_winning_count(card: &Card) -> usize {
-    card.revealed_numbers
-        .iter()
-        .filter(|&num| card.winning_numbers.contains(num))
-        .count()
+    card.winning_numbers
+        .intersection_count(&card.revealed_numbers)
+}
+
+/// How many points a card is worth for a given number of matches.
+#[derive(Debug, Clone, PartialEq)]
+enum ScoringRule {
+    /// One point for the first match, doubled for each one after it.
+    Doubling,
+    /// A fixed number of points per match.
+    Linear(usize),
+    /// 1, 1, 2, 3, 5, ... points for 1, 2, 3, 4, 5, ... matches.
+    Fibonacci,
+    /// Entry `n` is the score for `n` matches. Counts past the end of the
+    /// table score the last entry.
+    Table(Vec<usize>),
 }
 
-fn find_winning_numbers_score(cards: &[Card]) -> usize {
+impl ScoringRule {
+    fn score(&self, matches: usize) -> usize {
+        match self {
+            ScoringRule::Doubling | ScoringRule::Fibonacci if matches == 0 => 0,
+            ScoringRule::Doubling => 2_usize.pow(matches as u32 - 1),
+            ScoringRule::Linear(points) => matches * points,
+            ScoringRule::Fibonacci => {
+                let (mut a, mut b) = (1, 1);
+                for _ in 1..matches {
+                    (a, b) = (b, a + b);
+                }
+                a
+            }
+            ScoringRule::Table(table) => table.get(matches).or(table.last()).copied().unwrap_or(0),
+        }
+    }
+}
+
+fn find_winning_numbers_score(cards: &[Card], rule: &ScoringRule) -> usize {
     let mut total = 0;
 
     for card in cards {
-        let count = get_winning_count(card) as u32;
-        if count > 0 {
-            total += 2_usize.pow(count - 1);
-        }
+        total += rule.score(get_winning_count(card));
     }
 
     total
 }
 
+/// Which later cards a winning card copies, and how many copies each gets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CopyRule {
+    /// One copy of each of the next `matches` cards for every copy held.
+    Next,
+    /// Like `Next`, but only every k-th card from this one.
+    EveryKth(NonZeroUsize),
+    /// Like `Next`, but each card along receives the previous card's copies
+    /// divided by the given factor.
+    Decay(NonZeroU128),
+}
+
+impl CopyRule {
+    /// Returns `(offset, copies)` pairs for a card held `count` times.
+    fn copies(&self, matches: usize, count: u128) -> Vec<(usize, u128)> {
+        match *self {
+            CopyRule::Next => (1..matches + 1).map(|offset| (offset, count)).collect(),
+            CopyRule::EveryKth(k) => (1..matches + 1).map(|n| (n * k.get(), count)).collect(),
+            CopyRule::Decay(factor) => (1..matches + 1)
+                .scan(count, |copies, offset| {
+                    let current = *copies;
+                    *copies /= factor.get();
+                    Some((offset, current))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// What to do with copies won past the last card in the table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OverflowPolicy {
+    /// Drop the copies that would land past the end.
+    Clamp,
+    /// Refuse to count the cards at all.
+    Error,
+    /// Carry on from the first card. Those cards have already been scratched,
+    /// so the wrapped copies are counted but don't win any more copies.
+    Wrap,
+}
+
+#[derive(Debug, PartialEq)]
+enum CountError {
+    PastEnd { card: usize, matches: usize },
+    Overflow { card: usize },
+    TotalOverflow,
+    RepeatedId { card: usize },
+}
+
+impl fmt::Display for CountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CountError::PastEnd { card, matches } => write!(
+                f,
+                "card {} wins {} copies, which runs past the end of the table",
+                card, matches
+            ),
+            CountError::Overflow { card } => {
+                write!(f, "copy count overflowed while processing card {}", card)
+            }
+            CountError::TotalOverflow => write!(f, "total card count overflowed"),
+            CountError::RepeatedId { card } => {
+                write!(f, "card {} appears more than once", card)
+            }
+        }
+    }
+}
+
+/// What happened when one card was scratched. Cards are referred to by ID.
+#[derive(Debug, PartialEq)]
+struct TraceStep {
+    card: usize,
+    matches: usize,
+    copies: u128,
+    copies_to: Vec<(usize, u128)>,
+}
+
+/// The cards in ID order, with a lookup from ID back to position in the input.
+struct CardTable {
+    order: Vec<usize>,
+    index_of: HashMap<usize, usize>,
+    first: usize,
+    last: usize,
+}
+
+enum Target {
+    Card(usize),
+    Missing,
+    PastEnd,
+}
+
+impl CardTable {
+    fn new(cards: &[Card]) -> Result<Self, CountError> {
+        let mut order: Vec<usize> = (0..cards.len()).collect();
+        order.sort_by_key(|&i| cards[i].id);
+
+        let mut index_of = HashMap::new();
+        for (i, card) in cards.iter().enumerate() {
+            if index_of.insert(card.id, i).is_some() {
+                return Err(CountError::RepeatedId { card: card.id });
+            }
+        }
+
+        let first = order.first().map_or(0, |&i| cards[i].id);
+        let last = order.last().map_or(0, |&i| cards[i].id);
+
+        Ok(CardTable {
+            order,
+            index_of,
+            first,
+            last,
+        })
+    }
+
+    // Where a copy of the card `offset` IDs after `id` lands. Wrapping goes
+    // back to the lowest ID rather than to card 1.
+    fn target(&self, id: usize, offset: usize, policy: OverflowPolicy) -> Target {
+        let target = match policy {
+            OverflowPolicy::Wrap => {
+                self.first + (id + offset - self.first) % (self.last - self.first + 1)
+            }
+            _ if id + offset <= self.last => id + offset,
+            _ => return Target::PastEnd,
+        };
+
+        match self.index_of.get(&target) {
+            Some(&j) => Target::Card(j),
+            None => Target::Missing,
+        }
+    }
+}
+
 // I knew this was a Dynamic Programming problem, but I'm a bit rusty (hah!)
-fn count_cards_scratched(cards: &[Card]) -> u32 {
-    let mut counts = vec![1; cards.len()];
+//
+// Returns how many of each card ended up being scratched, in ID order,
+// calling `on_step` as each card is processed. Copies of an ID that isn't in
+// the table are lost.
+fn scratch_cards(
+    cards: &[Card],
+    rule: &CopyRule,
+    policy: OverflowPolicy,
+    mut on_step: impl FnMut(TraceStep),
+) -> Result<Vec<u128>, CountError> {
+    let table = CardTable::new(cards)?;
+    let mut counts: Vec<u128> = vec![1; cards.len()];
 
-    for (i, card) in cards.iter().enumerate() {
+    for &i in &table.order {
+        let card = &cards[i];
         let card_count = counts[i];
+        let matches = get_winning_count(card);
+        let mut copies_to = Vec::new();
+
+        for (offset, copies) in rule.copies(matches, card_count) {
+            let j = match table.target(card.id, offset, policy) {
+                Target::Card(j) => j,
+                Target::Missing => continue,
+                Target::PastEnd if policy == OverflowPolicy::Clamp => break,
+                Target::PastEnd => {
+                    return Err(CountError::PastEnd {
+                        card: card.id,
+                        matches,
+                    })
+                }
+            };
+
+            counts[j] = counts[j]
+                .checked_add(copies)
+                .ok_or(CountError::Overflow { card: card.id })?;
+            copies_to.push((cards[j].id, copies));
+        }
+
+        on_step(TraceStep {
+            card: card.id,
+            matches,
+            copies: card_count,
+            copies_to,
+        });
+    }
 
-        for offset in 1..get_winning_count(card) + 1 {
-            counts[i + offset] += card_count;
+    Ok(table.order.iter().map(|&i| counts[i]).collect())
+}
+
+fn count_cards_scratched(
+    cards: &[Card],
+    rule: &CopyRule,
+    policy: OverflowPolicy,
+) -> Result<u128, CountError> {
+    let counts = scratch_cards(cards, rule, policy, |_| {})?;
+
+    counts.iter().try_fold(0_u128, |acc, &count| {
+        acc.checked_add(count).ok_or(CountError::TotalOverflow)
+    })
+}
+
+// Scratches every copy one at a time instead of in bulk, as a check on
+// `scratch_cards`. Only rules where each copy wins independently (`Next` and
+// `EveryKth`) give the same answer, and it's far too slow for long chains.
+fn simulate_cards_scratched(cards: &[Card], rule: &CopyRule, policy: OverflowPolicy) -> u128 {
+    let table = CardTable::new(cards).unwrap();
+    let mut held: Vec<u128> = vec![1; cards.len()];
+
+    for &i in &table.order {
+        let matches = get_winning_count(&cards[i]);
+        let mut scratched = 0;
+
+        while scratched < held[i] {
+            for (offset, copies) in rule.copies(matches, 1) {
+                match table.target(cards[i].id, offset, policy) {
+                    Target::Card(j) => held[j] += copies,
+                    Target::Missing => continue,
+                    Target::PastEnd => break,
+                }
+            }
+            scratched += 1;
         }
     }
 
-    counts.iter().sum()
+    held.iter().sum()
+}
+
+fn format_trace(steps: &[TraceStep]) -> String {
+    let mut output = String::new();
+
+    for step in steps {
+        let copies_to: Vec<String> = step
+            .copies_to
+            .iter()
+            .map(|(card, copies)| format!("{} (+{})", card, copies))
+            .collect();
+
+        output.push_str(&format!(
+            "card {}: {} matches, {} copies, copies to [{}]\n",
+            step.card,
+            step.matches,
+            step.copies,
+            copies_to.join(", ")
+        ));
+    }
+
+    output
+}
+
+fn format_trace_csv(steps: &[TraceStep], counts: &[u128]) -> String {
+    let mut output = String::from("card,matches,copies_when_processed,final_copies,copies_to\n");
+
+    for (step, count) in steps.iter().zip(counts) {
+        let copies_to: Vec<String> = step
+            .copies_to
+            .iter()
+            .map(|(card, copies)| format!("{}:{}", card, copies))
+            .collect();
+
+        output.push_str(&format!(
+            "{},{},{},{},{}\n",
+            step.card,
+            step.matches,
+            step.copies,
+            count,
+            copies_to.join(";")
+        ));
+    }
+
+    output
+}
+
+fn parse_overflow_policy(arg: &str) -> OverflowPolicy {
+    match arg {
+        "clamp" => OverflowPolicy::Clamp,
+        "error" => OverflowPolicy::Error,
+        "wrap" => OverflowPolicy::Wrap,
+        other => panic!("Unexpected overflow policy: {}", other),
+    }
+}
+
+fn parse_scoring_rule(arg: &str) -> ScoringRule {
+    let (name, param) = arg.split_once(':').unwrap_or((arg, ""));
+    match name {
+        "doubling" => ScoringRule::Doubling,
+        "linear" => ScoringRule::Linear(param.parse().unwrap()),
+        "fibonacci" => ScoringRule::Fibonacci,
+        "table" => ScoringRule::Table(param.split(',').map(|n| n.parse().unwrap()).collect()),
+        other => panic!("Unexpected scoring rule: {}", other),
+    }
+}
+
+fn parse_copy_rule(arg: &str) -> CopyRule {
+    let (name, param) = arg.split_once(':').unwrap_or((arg, ""));
+    match name {
+        "next" => CopyRule::Next,
+        // Every 0th card would be the card itself, copied forever.
+        "every" => match NonZeroUsize::new(param.parse().unwrap()) {
+            Some(k) => CopyRule::EveryKth(k),
+            None => panic!("Unexpected copy rule: {}", arg),
+        },
+        "decay" => match NonZeroU128::new(param.parse().unwrap()) {
+            Some(factor) => CopyRule::Decay(factor),
+            None => panic!("Unexpected copy rule: {}", arg),
+        },
+        other => panic!("Unexpected copy rule: {}", other),
+    }
 }
 
 fn main() {
     let input = include_str!("../input.txt");
     let cards = parse_input(input);
-    println!("part 1: {}", find_winning_numbers_score(&cards));
-    println!("part 2: {}", count_cards_scratched(&cards));
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let flag = |name: &str| args.iter().find_map(|arg| arg.strip_prefix(name));
+
+    let policy = flag("--overflow=")
+        .map(parse_overflow_policy)
+        .unwrap_or(OverflowPolicy::Error);
+    let scoring_rule = flag("--scoring=")
+        .map(parse_scoring_rule)
+        .unwrap_or(ScoringRule::Doubling);
+    let copy_rule = flag("--copy=")
+        .map(parse_copy_rule)
+        .unwrap_or(CopyRule::Next);
+
+    for card in &cards {
+        for duplicate in &card.duplicates {
+            eprintln!("warning: card {}: {}", card.id, duplicate);
+        }
+    }
+
+    for err in validate_card_ids(&cards) {
+        eprintln!("warning: {}", err);
+    }
+
+    if args.iter().any(|arg| arg == "trace") {
+        let mut steps = Vec::new();
+        let counts = match scratch_cards(&cards, &copy_rule, policy, |step| steps.push(step)) {
+            Ok(counts) => counts,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+
+        if args.iter().any(|arg| arg == "--csv") {
+            print!("{}", format_trace_csv(&steps, &counts));
+        } else {
+            print!("{}", format_trace(&steps));
+            println!("total: {}", counts.iter().sum::<u128>());
+            println!(
+                "naive simulation: {}",
+                simulate_cards_scratched(&cards, &copy_rule, policy)
+            );
+        }
+        return;
+    }
+
+    println!(
+        "part 1: {}",
+        find_winning_numbers_score(&cards, &scoring_rule)
+    );
+    match count_cards_scratched(&cards, &copy_rule, policy) {
+        Ok(total) => println!("part 2: {}", total),
+        Err(err) => println!("part 2: {}", err),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    const SAMPLE_INPUT: &'static str = r"
+    const SAMPLE_INPUT: &str = r"
         Card 1: 41 48 83 86 17 | 83 86  6 31 17  9 48 53
         Card 2: 13 32 20 16 61 | 61 30 68 82 17 32 24 19
         Card 3:  1 21 53 59 44 | 69 82 63 72 16 21 14  1
@@ -83,26 +642,242 @@ mod tests {
 
     #[test]
     fn it_should_parse_input() {
-        let cards = parse_input(&SAMPLE_INPUT);
+        let cards = parse_input(SAMPLE_INPUT);
 
-        assert_eq!(cards[0].winning_numbers[3], 86);
-        assert_eq!(cards[0].revealed_numbers[4], 17);
+        assert!(cards[0].winning_numbers.contains(86));
+        assert!(cards[0].revealed_numbers.contains(17));
 
-        assert_eq!(cards[5].winning_numbers[4], 72);
-        assert_eq!(cards[5].revealed_numbers[7], 11);
+        assert!(cards[5].winning_numbers.contains(72));
+        assert!(cards[5].revealed_numbers.contains(11));
+        assert!(!cards[5].revealed_numbers.contains(72));
     }
 
     #[test]
     fn it_should_find_the_total_of_the_winning_numbers() {
-        let cards = parse_input(&SAMPLE_INPUT);
-        let total = find_winning_numbers_score(&cards);
+        let cards = parse_input(SAMPLE_INPUT);
+        let total = find_winning_numbers_score(&cards, &ScoringRule::Doubling);
         assert_eq!(total, 13);
     }
 
     #[test]
     fn it_should_count_the_number_of_scratch_cards() {
-        let cards = parse_input(&SAMPLE_INPUT);
-        let total = count_cards_scratched(&cards);
-        assert_eq!(total, 30);
+        let cards = parse_input(SAMPLE_INPUT);
+        let total = count_cards_scratched(&cards, &CopyRule::Next, OverflowPolicy::Error);
+        assert_eq!(total, Ok(30));
+    }
+
+    #[test]
+    fn it_should_report_duplicate_numbers() {
+        let cards = parse_input("Card 1: 41 48 41 | 48 200 48 9 48");
+
+        assert!(matches!(
+            &cards[0].winning_numbers,
+            NumberSet::Bits { min: 9, words } if words.len() == 3
+        ));
+        assert_eq!(get_winning_count(&cards[0]), 1);
+        assert_eq!(
+            cards[0].duplicates,
+            [
+                Duplicate {
+                    side: Side::Winning,
+                    number: 41
+                },
+                Duplicate {
+                    side: Side::Revealed,
+                    number: 48
+                },
+                Duplicate {
+                    side: Side::Revealed,
+                    number: 48
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_should_hash_widely_spread_numbers() {
+        let cards = parse_input(
+            "
+            Card 1: 4000000000 17 5 | 5 17 4000000000 8
+            Card 2: 1 2 3 | 3 4000000000 2
+            ",
+        );
+
+        assert!(matches!(cards[0].winning_numbers, NumberSet::Hashed(_)));
+        assert!(cards[1].revealed_numbers.contains(4000000000));
+        assert!(!cards[1].revealed_numbers.contains(1));
+        assert_eq!(get_winning_count(&cards[0]), 3);
+        assert_eq!(get_winning_count(&cards[1]), 2);
+    }
+
+    #[test]
+    fn it_should_apply_the_overflow_policy_past_the_end_of_the_table() {
+        let cards = parse_input(
+            "
+            Card 1: 1 2 3 | 1 2 3
+            Card 2: 5 | 5
+            ",
+        );
+
+        assert_eq!(
+            count_cards_scratched(&cards, &CopyRule::Next, OverflowPolicy::Clamp),
+            Ok(3)
+        );
+        assert_eq!(
+            count_cards_scratched(&cards, &CopyRule::Next, OverflowPolicy::Error),
+            Err(CountError::PastEnd {
+                card: 1,
+                matches: 3
+            })
+        );
+        assert_eq!(
+            count_cards_scratched(&cards, &CopyRule::Next, OverflowPolicy::Wrap),
+            Ok(8)
+        );
+    }
+
+    // Card `i` of `n` wins a copy of every card after it, so the total is
+    // `2^n - 1`.
+    fn doubling_cards(n: usize) -> String {
+        (0..n)
+            .map(|i| {
+                let numbers: Vec<String> = (1..n - i).map(|num| num.to_string()).collect();
+                let numbers = numbers.join(" ");
+                format!("Card {}: {} | {}\n", i + 1, numbers, numbers)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_should_count_past_u32() {
+        let cards = parse_input(&doubling_cards(40));
+        let total = count_cards_scratched(&cards, &CopyRule::Next, OverflowPolicy::Error);
+        assert_eq!(total, Ok((1 << 40) - 1));
+    }
+
+    #[test]
+    fn it_should_report_counts_that_overflow_u128() {
+        let cards = parse_input(&doubling_cards(130));
+        let total = count_cards_scratched(&cards, &CopyRule::Next, OverflowPolicy::Error);
+        assert!(matches!(total, Err(CountError::Overflow { .. })));
+    }
+
+    #[test]
+    fn it_should_report_totals_that_overflow_u128() {
+        // Every card's count fits, but the extra card tips the sum over.
+        let input = doubling_cards(128) + "Card 129: 1 | 2\n";
+        let cards = parse_input(&input);
+        let total = count_cards_scratched(&cards, &CopyRule::Next, OverflowPolicy::Error);
+        assert_eq!(total, Err(CountError::TotalOverflow));
+    }
+
+    #[test]
+    fn it_should_score_with_each_scoring_rule() {
+        let cards = parse_input(SAMPLE_INPUT);
+        let score = |rule| find_winning_numbers_score(&cards, &rule);
+
+        assert_eq!(score(ScoringRule::Doubling), 13);
+        assert_eq!(score(ScoringRule::Linear(2)), 18);
+        assert_eq!(score(ScoringRule::Fibonacci), 6);
+        assert_eq!(score(ScoringRule::Table(vec![0, 1, 3, 6])), 13);
+        assert_eq!(score(ScoringRule::Table(vec![1, 2, 3, 4])), 14);
+    }
+
+    #[test]
+    fn it_should_count_cards_with_each_copy_rule() {
+        let cards = parse_input(SAMPLE_INPUT);
+        let count = |rule| count_cards_scratched(&cards, &rule, OverflowPolicy::Clamp);
+
+        assert_eq!(count(CopyRule::Next), Ok(30));
+        assert_eq!(count(parse_copy_rule("every:1")), Ok(30));
+        assert_eq!(count(parse_copy_rule("every:2")), Ok(14));
+        assert_eq!(count(parse_copy_rule("decay:2")), Ok(19));
+    }
+
+    #[test]
+    fn it_should_trace_each_card() {
+        let cards = parse_input(SAMPLE_INPUT);
+        let mut steps = Vec::new();
+        let counts = scratch_cards(&cards, &CopyRule::Next, OverflowPolicy::Error, |step| {
+            steps.push(step)
+        });
+
+        assert_eq!(counts, Ok(vec![1, 2, 4, 8, 14, 1]));
+        assert_eq!(
+            steps[1],
+            TraceStep {
+                card: 2,
+                matches: 2,
+                copies: 2,
+                copies_to: vec![(3, 2), (4, 2)],
+            }
+        );
+
+        let csv = format_trace_csv(&steps, &counts.unwrap());
+        assert_eq!(csv.lines().nth(3), Some("3,2,4,4,4:4;5:4"));
+    }
+
+    #[test]
+    fn it_should_match_the_naive_simulation() {
+        let cards = parse_input(SAMPLE_INPUT);
+
+        for rule in [CopyRule::Next, parse_copy_rule("every:2")] {
+            assert_eq!(
+                count_cards_scratched(&cards, &rule, OverflowPolicy::Clamp),
+                Ok(simulate_cards_scratched(
+                    &cards,
+                    &rule,
+                    OverflowPolicy::Clamp
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_keep_card_ids() {
+        let cards = parse_input(SAMPLE_INPUT);
+        let ids: Vec<usize> = cards.iter().map(|card| card.id).collect();
+        assert_eq!(ids, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(validate_card_ids(&cards), []);
+    }
+
+    #[test]
+    fn it_should_count_out_of_order_cards_by_id() {
+        let mut lines: Vec<&str> = SAMPLE_INPUT.trim().lines().collect();
+        lines.reverse();
+        let cards = parse_input(&lines.join("\n"));
+
+        assert_eq!(
+            validate_card_ids(&cards)[0],
+            CardIdError::OutOfOrder { id: 5, previous: 6 }
+        );
+
+        let total = count_cards_scratched(&cards, &CopyRule::Next, OverflowPolicy::Error);
+        assert_eq!(total, Ok(30));
+    }
+
+    #[test]
+    fn it_should_lose_copies_of_missing_cards() {
+        let input: Vec<&str> = SAMPLE_INPUT
+            .lines()
+            .filter(|line| !line.contains("Card 4"))
+            .collect();
+        let cards = parse_input(&input.join("\n"));
+
+        assert_eq!(validate_card_ids(&cards), [CardIdError::Missing { id: 4 }]);
+
+        let total = count_cards_scratched(&cards, &CopyRule::Next, OverflowPolicy::Error);
+        assert_eq!(total, Ok(14));
+    }
+
+    #[test]
+    fn it_should_refuse_repeated_card_ids() {
+        let cards = parse_input("Card 1: 1 | 1\nCard 1: 2 | 3\nCard 2: 4 | 5");
+
+        assert_eq!(validate_card_ids(&cards), [CardIdError::Repeated { id: 1 }]);
+        assert_eq!(
+            count_cards_scratched(&cards, &CopyRule::Next, OverflowPolicy::Error),
+            Err(CountError::RepeatedId { card: 1 })
+        );
     }
 }
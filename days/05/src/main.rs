@@ -1,45 +1,117 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::fmt;
+use std::mem;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
 struct Almanac {
     seeds: Vec<i64>,
-    maps: Vec<Vec<Range>>,
+    maps: Vec<Map>,
+}
+
+/// One `<source>-to-<destination> map:` section of the almanac.
+struct Map {
+    source: String,
+    destination: String,
+    entries: Vec<Entry>,
+    ranges: Vec<Range>,
+}
+
+/// A `destination source length` line exactly as written, kept so that
+/// `validate` can point at it.
+struct Entry {
+    line: usize,
+    destination: i64,
+    source: i64,
+    length: i64,
+}
+
+impl Entry {
+    // `None` if the numbers are too big to work with.
+    fn range(&self) -> Option<Range> {
+        let end = self.source.checked_add(self.length)?;
+        let offset = self.destination.checked_sub(self.source)?;
+        let image_end = self.destination.checked_add(self.length)?;
+
+        if end > DOMAIN_END || image_end > DOMAIN_END {
+            return None;
+        }
+
+        Some(Range {
+            offset,
+            start: self.source,
+            end,
+        })
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 struct Range {
     offset: i64,
     start: i64,
     end: i64,
 }
 
-fn parse_input(input: &str) -> Almanac {
-    let (seeds_str, maps_str) = input.trim().split_once("\n").unwrap();
-    let seeds: Vec<i64> = seeds_str
-        .split(':')
-        .nth(1)
-        .unwrap()
-        .split_whitespace()
-        .map(|v| v.parse().unwrap())
-        .collect();
+impl Range {
+    /// Just the values `start..end`, with nothing added to them.
+    fn span(start: i64, end: i64) -> Self {
+        Range {
+            offset: 0,
+            start,
+            end,
+        }
+    }
 
-    let mut maps: Vec<Vec<Range>> = Vec::new();
+    // Ranges are half-open, like the source ranges in the almanac: a map line
+    // `50 98 2` covers 98 and 99 but not 100.
+    fn contains(&self, value: i64) -> bool {
+        self.start <= value && value < self.end
+    }
+
+    fn overlaps(&self, start: i64, end: i64) -> bool {
+        self.start.max(start) < self.end.min(end)
+    }
+}
+
+fn parse_input(input: &str) -> Almanac {
+    let mut seeds: Vec<i64> = Vec::new();
+    let mut maps: Vec<Map> = Vec::new();
 
-    for line in maps_str.lines() {
+    for (i, line) in input.lines().enumerate() {
         let line = line.trim();
-        if let Some(first_char) = line.chars().nth(0) {
+        if let Some(seeds_str) = line.strip_prefix("seeds:") {
+            seeds = seeds_str
+                .split_whitespace()
+                .map(|v| v.parse().unwrap())
+                .collect();
+        } else if let Some(first_char) = line.chars().next() {
             if first_char.is_alphabetic() {
-                maps.push(Vec::new());
+                let categories = line.trim_end_matches(':').trim_end_matches(" map");
+                let (source, destination) = categories.split_once("-to-").unwrap();
+                maps.push(Map {
+                    source: source.to_string(),
+                    destination: destination.to_string(),
+                    entries: Vec::new(),
+                    ranges: Vec::new(),
+                });
             } else if first_char.is_numeric() {
-                if let Some(ranges) = maps.last_mut() {
+                if let Some(map) = maps.last_mut() {
                     let numbers: Vec<&str> = line.split_whitespace().collect();
 
-                    let dst: i64 = numbers[0].parse().unwrap();
-                    let src: i64 = numbers[1].parse().unwrap();
-                    let len: i64 = numbers[2].parse().unwrap();
+                    let entry = Entry {
+                        line: i + 1,
+                        destination: numbers[0].parse().unwrap(),
+                        source: numbers[1].parse().unwrap(),
+                        length: numbers[2].parse().unwrap(),
+                    };
 
-                    ranges.push(Range {
-                        offset: dst - src,
-                        start: src,
-                        end: src + len,
-                    });
+                    if let Some(range) = entry.range() {
+                        map.ranges.push(range);
+                    }
+                    map.entries.push(entry);
                 }
             }
         }
@@ -48,13 +120,197 @@ fn parse_input(input: &str) -> Almanac {
     Almanac { seeds, maps }
 }
 
-fn find_nearest_for_planting(almanac: &Almanac) -> i64 {
-    almanac
+#[derive(Debug, PartialEq)]
+enum Issue {
+    /// Values in both are mapped by the earlier `other` line.
+    Overlap {
+        line: usize,
+        other: usize,
+    },
+    /// Values in `start..end` pass through the stage unchanged.
+    Gap {
+        start: i64,
+        end: i64,
+    },
+    ZeroLength {
+        line: usize,
+    },
+    Overflow {
+        line: usize,
+    },
+}
+
+impl Issue {
+    // Gaps are normal in an almanac, everything else is a mistake.
+    fn is_error(&self) -> bool {
+        !matches!(self, Issue::Gap { .. })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Problem {
+    map: String,
+    issue: Issue,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} map: ", self.map)?;
+        match self.issue {
+            Issue::Overlap { line, other } => {
+                write!(f, "line {} overlaps line {}", line, other)
+            }
+            Issue::Gap { start, end } => write!(f, "{}..{} is not mapped", start, end),
+            Issue::ZeroLength { line } => write!(f, "line {} has zero length", line),
+            Issue::Overflow { line } => write!(f, "line {} is out of range", line),
+        }
+    }
+}
+
+fn validate(almanac: &Almanac) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    for map in &almanac.maps {
+        let mut issues = Vec::new();
+        let mut valid: Vec<(&Entry, Range)> = Vec::new();
+
+        for entry in &map.entries {
+            match entry.range() {
+                None => issues.push(Issue::Overflow { line: entry.line }),
+                Some(_) if entry.length == 0 => issues.push(Issue::ZeroLength { line: entry.line }),
+                Some(range) => {
+                    for (other, other_range) in &valid {
+                        if range.start < other_range.end && other_range.start < range.end {
+                            issues.push(Issue::Overlap {
+                                line: entry.line,
+                                other: other.line,
+                            });
+                        }
+                    }
+                    valid.push((entry, range));
+                }
+            }
+        }
+
+        valid.sort_by_key(|(_, range)| range.start);
+        let mut covered = valid.first().map_or(0, |(_, range)| range.start);
+        for (_, range) in &valid {
+            if range.start > covered {
+                issues.push(Issue::Gap {
+                    start: covered,
+                    end: range.start,
+                });
+            }
+            covered = covered.max(range.end);
+        }
+
+        let name = format!("{}-to-{}", map.source, map.destination);
+        problems.extend(issues.into_iter().map(|issue| Problem {
+            map: name.clone(),
+            issue,
+        }));
+    }
+
+    problems
+}
+
+#[derive(Debug, PartialEq)]
+enum CategoryError {
+    Unknown(String),
+    NoPath { from: String, to: String },
+    Broken { destination: String, next: String },
+}
+
+impl fmt::Display for CategoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CategoryError::Unknown(category) => write!(f, "no map mentions {}", category),
+            CategoryError::NoPath { from, to } => {
+                write!(f, "there is no way to map {} to {}", from, to)
+            }
+            CategoryError::Broken { destination, next } => write!(
+                f,
+                "a map to {} is followed by a map from {}",
+                destination, next
+            ),
+        }
+    }
+}
+
+impl Almanac {
+    /// Checks that the maps are listed in the order they should be applied.
+    fn validate_chain(&self) -> Result<(), CategoryError> {
+        for pair in self.maps.windows(2) {
+            if pair[0].destination != pair[1].source {
+                return Err(CategoryError::Broken {
+                    destination: pair[0].destination.clone(),
+                    next: pair[1].source.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finds the shortest sequence of maps taking `from` values to `to`
+    /// values, whatever order they were listed in.
+    fn path(&self, from: &str, to: &str) -> Result<Vec<&Map>, CategoryError> {
+        for category in [from, to] {
+            let known = self
+                .maps
+                .iter()
+                .any(|map| map.source == category || map.destination == category);
+            if !known {
+                return Err(CategoryError::Unknown(category.to_string()));
+            }
+        }
+
+        let mut reached_by: HashMap<&str, &Map> = HashMap::new();
+        let mut visited = HashSet::from([from]);
+        let mut queue = VecDeque::from([from]);
+
+        while let Some(category) = queue.pop_front() {
+            for map in self.maps.iter().filter(|map| map.source == category) {
+                if visited.insert(&map.destination) {
+                    reached_by.insert(&map.destination, map);
+                    queue.push_back(&map.destination);
+                }
+            }
+        }
+
+        let mut path = Vec::new();
+        let mut category = to;
+        while category != from {
+            let map = reached_by.get(category).ok_or(CategoryError::NoPath {
+                from: from.to_string(),
+                to: to.to_string(),
+            })?;
+            path.push(*map);
+            category = &map.source;
+        }
+        path.reverse();
+
+        Ok(path)
+    }
+
+    fn stages(&self) -> Result<Vec<&[Range]>, CategoryError> {
+        Ok(self
+            .path("seed", "location")?
+            .into_iter()
+            .map(|map| map.ranges.as_slice())
+            .collect())
+    }
+}
+
+fn find_nearest_for_planting(almanac: &Almanac) -> Result<i64, CategoryError> {
+    let stages = almanac.stages()?;
+
+    Ok(almanac
         .seeds
         .iter()
         .map(|&seed| {
-            almanac.maps.iter().fold(seed, |acc, cur| {
-                let maybe_range = cur.iter().find(|&r| acc > r.start && acc <= r.end);
+            stages.iter().fold(seed, |acc, cur| {
+                let maybe_range = cur.iter().find(|&r| r.contains(acc));
                 if let Some(range) = maybe_range {
                     acc + range.offset
                 } else {
@@ -63,87 +319,672 @@ fn find_nearest_for_planting(almanac: &Almanac) -> i64 {
             })
         })
         .min()
-        .unwrap()
+        .unwrap())
+}
+
+/// Scratch space for mapping one seed range through every stage, so that
+/// it can be reused from one seed range to the next.
+#[derive(Default)]
+struct Buffers {
+    seeds: Vec<Range>,
+    results: Vec<Range>,
+}
+
+// Sends every piece in `pending` through one stage, splitting it wherever the
+// first overlapping range stops, and pushes the results onto `mapped`. Pieces
+// no range covers pass through unchanged; returns how many values those were.
+fn map_stage(stage: &[Range], pending: &mut Vec<Range>, mapped: &mut Vec<Range>) -> i64 {
+    let mut unmapped = 0;
+
+    while let Some(Range { start, end, .. }) = pending.pop() {
+        match stage.iter().find(|range| range.overlaps(start, end)) {
+            Some(range) => {
+                if start < range.start {
+                    pending.push(Range::span(start, range.start));
+                }
+                if end > range.end {
+                    pending.push(Range::span(range.end, end));
+                }
+                mapped.push(Range::span(
+                    start.max(range.start) + range.offset,
+                    end.min(range.end) + range.offset,
+                ));
+            }
+            None => {
+                unmapped += end - start;
+                mapped.push(Range::span(start, end));
+            }
+        }
+    }
+
+    unmapped
 }
 
 // This is a travesty of code, but it's almost 2am and I don't care any more.
-// Also if there's a way to avoid allocating additional vectors I'm not seeing it.
-fn part2(almanac: &Almanac) -> i64 {
-    almanac
+fn nearest_in_seed_range(stages: &[&[Range]], chunk: &[i64], buffers: &mut Buffers) -> Option<i64> {
+    let Buffers { seeds, results } = buffers;
+    seeds.clear();
+    results.clear();
+
+    seeds.push(Range::span(chunk[0], chunk[0] + chunk[1]));
+
+    for stage in stages {
+        map_stage(stage, seeds, results);
+        mem::swap(seeds, results);
+    }
+
+    seeds.iter().map(|seed| seed.start).min()
+}
+
+fn part2(almanac: &Almanac) -> Result<i64, CategoryError> {
+    let stages = almanac.stages()?;
+    let mut buffers = Buffers::default();
+
+    Ok(almanac
         .seeds
         .chunks_exact(2)
-        .map(|chunk| {
-            let mut seeds = vec![Range {
-                start: chunk[0],
-                end: chunk[0] + chunk[1],
-                offset: 0,
-            }];
+        .filter_map(|chunk| nearest_in_seed_range(&stages, chunk, &mut buffers))
+        .min()
+        .unwrap())
+}
 
-            let mut results = Vec::new();
+// Each worker takes the next unclaimed seed range until there are none left,
+// so one huge seed range doesn't hold the others up behind it.
+fn part2_parallel(almanac: &Almanac, threads: usize) -> Result<i64, CategoryError> {
+    let stages = almanac.stages()?;
+    let chunks: Vec<&[i64]> = almanac.seeds.chunks_exact(2).collect();
+    let next = AtomicUsize::new(0);
 
-            for map in &almanac.maps {
-                while let Some(seed) = seeds.pop() {
-                    let Range {
-                        mut start, mut end, ..
-                    } = seed;
+    Ok(thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut buffers = Buffers::default();
+                    let mut nearest: Option<i64> = None;
 
-                    let mut split = false;
-                    for range in map {
-                        if range.end <= start || range.start >= end {
-                            continue;
+                    while let Some(chunk) = chunks.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        if let Some(location) = nearest_in_seed_range(&stages, chunk, &mut buffers)
+                        {
+                            nearest = Some(nearest.map_or(location, |n| n.min(location)));
                         }
+                    }
 
-                        if start < range.start {
-                            seeds.push(Range {
-                                start: start,
-                                end: range.start,
-                                offset: 0,
-                            });
-                            start = range.start;
-                        }
+                    nearest
+                })
+            })
+            .collect();
 
-                        if end > range.end {
-                            seeds.push(Range {
-                                start: range.end,
-                                end: end,
-                                offset: 0,
-                            });
-                            end = range.end;
-                        }
+        workers
+            .into_iter()
+            .filter_map(|worker| worker.join().unwrap())
+            .min()
+            .unwrap()
+    }))
+}
 
-                        results.push(Range {
-                            start: start + range.offset,
-                            end: end + range.offset,
-                            offset: 0,
-                        });
+// Composed maps cover every value from zero up to here. Almanac numbers are
+// never negative, and the headroom keeps `end + offset` from overflowing.
+const DOMAIN_END: i64 = i64::MAX / 4;
 
-                        split = true;
-                        break;
-                    }
+/// A single function made of contiguous, sorted segments covering
+/// `0..DOMAIN_END`, each adding its `offset` to the values inside it.
+#[derive(Debug, PartialEq)]
+struct PiecewiseMap {
+    segments: Vec<Range>,
+}
 
-                    if !split {
-                        results.push(seed);
-                    }
+impl PiecewiseMap {
+    fn identity() -> Self {
+        PiecewiseMap {
+            segments: vec![Range {
+                offset: 0,
+                start: 0,
+                end: DOMAIN_END,
+            }],
+        }
+    }
+
+    // Where ranges overlap, the one listed first wins, the same as applying
+    // the stage directly.
+    fn from_stage(ranges: &[Range]) -> Self {
+        let mut bounds: Vec<i64> = ranges
+            .iter()
+            .flat_map(|range| [range.start, range.end])
+            .chain([0, DOMAIN_END])
+            .filter(|&bound| (0..=DOMAIN_END).contains(&bound))
+            .collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        let segments = bounds
+            .windows(2)
+            .map(|pair| {
+                let offset = ranges
+                    .iter()
+                    .find(|range| range.contains(pair[0]))
+                    .map_or(0, |range| range.offset);
+
+                Range {
+                    offset,
+                    start: pair[0],
+                    end: pair[1],
                 }
+            })
+            .collect();
+
+        PiecewiseMap::merged(segments)
+    }
 
-                (seeds, results) = (results, seeds);
+    fn merged(segments: Vec<Range>) -> Self {
+        let mut merged: Vec<Range> = Vec::with_capacity(segments.len());
+
+        for segment in segments {
+            match merged.last_mut() {
+                Some(last) if last.offset == segment.offset && last.end == segment.start => {
+                    last.end = segment.end;
+                }
+                _ => merged.push(segment),
             }
+        }
 
-            seeds
-        })
-        .flatten()
-        .map(|seed| seed.start)
+        PiecewiseMap { segments: merged }
+    }
+
+    fn segment_index(&self, value: i64) -> usize {
+        self.segments
+            .partition_point(|segment| segment.end <= value)
+    }
+
+    fn lookup(&self, value: i64) -> i64 {
+        match self.segments.get(self.segment_index(value)) {
+            Some(segment) if segment.contains(value) => value + segment.offset,
+            _ => value,
+        }
+    }
+
+    /// Applies `self` and then `next`.
+    fn then(&self, next: &PiecewiseMap) -> PiecewiseMap {
+        let mut segments = Vec::new();
+
+        for segment in &self.segments {
+            let (mut start, end) = (segment.start + segment.offset, segment.end + segment.offset);
+            let mut i = next.segment_index(start);
+
+            // Split this segment's image wherever `next` changes offset.
+            while start < end {
+                let (split, offset) = match next.segments.get(i) {
+                    Some(n) if n.start <= start => (n.end.min(end), n.offset),
+                    Some(n) => (n.start.min(end), 0),
+                    None => (end, 0),
+                };
+
+                segments.push(Range {
+                    offset: segment.offset + offset,
+                    start: start - segment.offset,
+                    end: split - segment.offset,
+                });
+
+                start = split;
+                i += 1;
+            }
+        }
+
+        PiecewiseMap::merged(segments)
+    }
+
+    /// The lowest value `start..end` maps to.
+    fn min_image(&self, start: i64, end: i64) -> Option<i64> {
+        self.segments[self.segment_index(start)..]
+            .iter()
+            .take_while(|segment| segment.start < end)
+            .map(|segment| segment.start.max(start) + segment.offset)
+            .min()
+    }
+}
+
+fn compose_path(almanac: &Almanac, from: &str, to: &str) -> Result<PiecewiseMap, CategoryError> {
+    let path = almanac.path(from, to)?;
+
+    Ok(path.iter().fold(PiecewiseMap::identity(), |acc, map| {
+        acc.then(&PiecewiseMap::from_stage(&map.ranges))
+    }))
+}
+
+fn compose_almanac(almanac: &Almanac) -> Result<PiecewiseMap, CategoryError> {
+    compose_path(almanac, "seed", "location")
+}
+
+fn query(almanac: &Almanac, from: &str, to: &str, values: &[i64]) -> Result<String, CategoryError> {
+    let path = almanac.path(from, to)?;
+    let map = compose_path(almanac, from, to)?;
+
+    let mut categories = vec![from];
+    categories.extend(path.iter().map(|map| map.destination.as_str()));
+    let mut output = categories.join(" -> ") + "\n";
+
+    if values.is_empty() {
+        for segment in &map.segments {
+            output.push_str(&format!(
+                "{}..{}: {:+}\n",
+                segment.start, segment.end, segment.offset
+            ));
+        }
+    }
+
+    for &value in values {
+        output.push_str(&format!("{} -> {}\n", value, map.lookup(value)));
+    }
+
+    Ok(output)
+}
+
+fn find_nearest_composed(almanac: &Almanac, map: &PiecewiseMap) -> i64 {
+    almanac
+        .seeds
+        .iter()
+        .map(|&seed| map.lookup(seed))
+        .min()
+        .unwrap()
+}
+
+fn find_nearest_range_composed(almanac: &Almanac, map: &PiecewiseMap) -> i64 {
+    almanac
+        .seeds
+        .chunks_exact(2)
+        .filter_map(|chunk| map.min_image(chunk[0], chunk[0] + chunk[1]))
         .min()
         .unwrap()
 }
 
+// Offsets are ignored, so this is only for plain spans.
+fn merge_intervals(mut intervals: Vec<Range>) -> Vec<Range> {
+    intervals.sort_by_key(|interval| interval.start);
+
+    let mut merged: Vec<Range> = Vec::with_capacity(intervals.len());
+    for interval in intervals {
+        match merged.last_mut() {
+            Some(last) if interval.start <= last.end => last.end = last.end.max(interval.end),
+            _ => merged.push(interval),
+        }
+    }
+
+    merged
+}
+
+// Walks the stages backwards, undoing each segment's offset, and returns the
+// pieces of the first stage's input that end up in `target`, each with the
+// total offset it picks up on the way.
+fn inverse_pieces(stages: &[PiecewiseMap], target: Range) -> Vec<Range> {
+    let mut pieces = vec![target];
+
+    for stage in stages.iter().rev() {
+        let mut previous = Vec::new();
+
+        for piece in pieces {
+            for segment in &stage.segments {
+                let start = piece.start.max(segment.start + segment.offset);
+                let end = piece.end.min(segment.end + segment.offset);
+
+                if start < end {
+                    previous.push(Range {
+                        offset: piece.offset + segment.offset,
+                        start: start - segment.offset,
+                        end: end - segment.offset,
+                    });
+                }
+            }
+        }
+
+        pieces = previous;
+    }
+
+    pieces
+}
+
+fn stage_maps(almanac: &Almanac) -> Result<Vec<PiecewiseMap>, CategoryError> {
+    Ok(almanac
+        .stages()?
+        .into_iter()
+        .map(PiecewiseMap::from_stage)
+        .collect())
+}
+
+/// Every seed that ends up in one of the `locations`.
+fn find_seeds_for(almanac: &Almanac, locations: Range) -> Result<Vec<Range>, CategoryError> {
+    let pieces = inverse_pieces(&stage_maps(almanac)?, locations);
+    Ok(merge_intervals(
+        pieces
+            .into_iter()
+            .map(|piece| Range::span(piece.start, piece.end))
+            .collect(),
+    ))
+}
+
+fn seed_ranges(almanac: &Almanac) -> Vec<Range> {
+    almanac
+        .seeds
+        .chunks_exact(2)
+        .map(|chunk| Range::span(chunk[0], chunk[0] + chunk[1]))
+        .collect()
+}
+
+// Searches the locations from the lowest up, mapping each piece back to the
+// seeds that reach it, until no later piece can beat the best seed found.
+fn find_nearest_range_reverse(almanac: &Almanac) -> Result<i64, CategoryError> {
+    let seeds = seed_ranges(almanac);
+
+    let everywhere = Range::span(0, DOMAIN_END);
+    let mut pieces = inverse_pieces(&stage_maps(almanac)?, everywhere);
+    pieces.sort_by_key(|piece| piece.start + piece.offset);
+
+    let mut nearest: Option<i64> = None;
+    for piece in pieces {
+        let offset = piece.offset;
+        if nearest.is_some_and(|nearest| piece.start + offset >= nearest) {
+            break;
+        }
+
+        for seed in &seeds {
+            let start = piece.start.max(seed.start);
+            if start < piece.end.min(seed.end) {
+                nearest = Some(nearest.map_or(start + offset, |n| n.min(start + offset)));
+            }
+        }
+    }
+
+    Ok(nearest.unwrap())
+}
+
+/// The seed ranges' image in one category.
+#[derive(Debug, PartialEq)]
+struct StageImage {
+    category: String,
+    intervals: Vec<Range>,
+    /// How many values the map into this category left unchanged, or `None`
+    /// for the seeds themselves.
+    unmapped: Option<i64>,
+}
+
+impl StageImage {
+    fn coverage(&self) -> i64 {
+        self.intervals
+            .iter()
+            .map(|interval| interval.end - interval.start)
+            .sum()
+    }
+}
+
+// Splits the intervals exactly as `part2` does, and also counts whatever no
+// range matches.
+fn apply_stage(ranges: &[Range], intervals: &[Range]) -> (Vec<Range>, i64) {
+    let mut pending = intervals.to_vec();
+    let mut image = Vec::new();
+    let unmapped = map_stage(ranges, &mut pending, &mut image);
+
+    (merge_intervals(image), unmapped)
+}
+
+fn seed_images(almanac: &Almanac) -> Result<Vec<StageImage>, CategoryError> {
+    let mut images = vec![StageImage {
+        category: "seed".to_string(),
+        intervals: merge_intervals(seed_ranges(almanac)),
+        unmapped: None,
+    }];
+
+    for map in almanac.path("seed", "location")? {
+        let (intervals, unmapped) = apply_stage(&map.ranges, &images.last().unwrap().intervals);
+        images.push(StageImage {
+            category: map.destination.clone(),
+            intervals,
+            unmapped: Some(unmapped),
+        });
+    }
+
+    Ok(images)
+}
+
+fn format_images(images: &[StageImage]) -> String {
+    let mut output = String::new();
+
+    for image in images {
+        output.push_str(&format!(
+            "{}: {} intervals, {} values",
+            image.category,
+            image.intervals.len(),
+            image.coverage()
+        ));
+        if let Some(unmapped) = image.unmapped {
+            output.push_str(&format!(", {} unmapped", unmapped));
+        }
+        output.push('\n');
+    }
+
+    output
+}
+
+// Category names come straight from the almanac headers.
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn images_to_json(images: &[StageImage]) -> String {
+    let stages: Vec<String> = images
+        .iter()
+        .map(|image| {
+            let intervals: Vec<String> = image
+                .intervals
+                .iter()
+                .map(|interval| format!("[{}, {}]", interval.start, interval.end))
+                .collect();
+            let unmapped = image
+                .unmapped
+                .map_or("null".to_string(), |unmapped| unmapped.to_string());
+
+            format!(
+                "  {{\"category\": \"{}\", \"intervals\": [{}], \"count\": {}, \"coverage\": {}, \"unmapped\": {}}}",
+                json_escape(&image.category),
+                intervals.join(", "),
+                image.intervals.len(),
+                image.coverage(),
+                unmapped
+            )
+        })
+        .collect();
+
+    format!("[\n{}\n]\n", stages.join(",\n"))
+}
+
+// A xorshift generator, so generated almanacs are reproducible without
+// pulling in a crate.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: i64) -> i64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as i64
+    }
+}
+
+// Shaped like a real almanac but with many more seed ranges and map lines.
+fn generate_almanac(rng: &mut Rng, seed_ranges: usize, lines_per_map: usize) -> String {
+    const MAX: i64 = 4_000_000_000;
+    let categories = [
+        "seed",
+        "soil",
+        "fertilizer",
+        "water",
+        "light",
+        "temperature",
+        "humidity",
+        "location",
+    ];
+
+    let seeds: Vec<String> = (0..seed_ranges)
+        .map(|_| format!("{} {}", rng.below(MAX), rng.below(MAX / 100)))
+        .collect();
+    let mut almanac = format!("seeds: {}\n", seeds.join(" "));
+
+    for pair in categories.windows(2) {
+        almanac.push_str(&format!("\n{}-to-{} map:\n", pair[0], pair[1]));
+        for _ in 0..lines_per_map {
+            let (dst, src, len) = (rng.below(MAX), rng.below(MAX), rng.below(MAX / 50));
+            almanac.push_str(&format!("{} {} {}\n", dst, src, len));
+        }
+    }
+
+    almanac
+}
+
+fn bench() {
+    let almanac = parse_input(&generate_almanac(&mut Rng(0x5eed), 20_000, 500));
+
+    let available = thread::available_parallelism().map_or(1, |n| n.get());
+    println!("available parallelism: {}", available);
+
+    let start = Instant::now();
+    let expected = part2(&almanac).unwrap();
+    let baseline = start.elapsed();
+    println!("sequential: {:?}", baseline);
+
+    for threads in [1, 2, 4, 8] {
+        let start = Instant::now();
+        let result = part2_parallel(&almanac, threads).unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(result, expected);
+
+        println!(
+            "{} threads: {:?} ({:.2}x)",
+            threads,
+            elapsed,
+            baseline.as_secs_f64() / elapsed.as_secs_f64()
+        );
+    }
+}
+
+// Errors go to stderr with a failing status, so scripts can tell a refused
+// solve from a successful one.
+fn fail(err: CategoryError) -> ! {
+    eprintln!("{}", err);
+    process::exit(1);
+}
+
 fn main() {
     let input = include_str!("../input.txt");
-    let almanac = parse_input(&input);
-    let part_1_result = find_nearest_for_planting(&almanac);
+    let almanac = parse_input(input);
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Err(err) = almanac.validate_chain() {
+        eprintln!("warning: {}", err);
+    }
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["query", from, to, values @ ..] => {
+            let values: Vec<i64> = values.iter().map(|v| v.parse().unwrap()).collect();
+            match query(&almanac, from, to, &values) {
+                Ok(output) => print!("{}", output),
+                Err(err) => fail(err),
+            }
+            return;
+        }
+        ["inverse", location] | ["inverse", location, _] => {
+            let start: i64 = location.parse().unwrap();
+            let end: i64 = args.get(2).map_or(start + 1, |end| end.parse().unwrap());
+            match find_seeds_for(&almanac, Range::span(start, end)) {
+                Ok(seeds) => {
+                    for seeds in seeds {
+                        println!("{}..{}", seeds.start, seeds.end);
+                    }
+                }
+                Err(err) => fail(err),
+            }
+            return;
+        }
+        // Applies each stage in turn instead of composing them, as a cross-check.
+        ["direct"] => {
+            match find_nearest_for_planting(&almanac).and_then(|p1| Ok((p1, part2(&almanac)?))) {
+                Ok((part_1, part_2)) => {
+                    println!("part 1: {}", part_1);
+                    println!("part 2: {}", part_2);
+                }
+                Err(err) => fail(err),
+            }
+            return;
+        }
+        ["parallel"] | ["parallel", _] => {
+            let threads = args.get(1).map_or_else(
+                || thread::available_parallelism().map_or(1, |n| n.get()),
+                |threads| threads.parse().unwrap(),
+            );
+            match part2_parallel(&almanac, threads) {
+                Ok(nearest) => println!("part 2: {}", nearest),
+                Err(err) => fail(err),
+            }
+            return;
+        }
+        // Run with `--release` for meaningful numbers.
+        ["bench"] => {
+            bench();
+            return;
+        }
+        ["reverse"] => {
+            match find_nearest_range_reverse(&almanac) {
+                Ok(nearest) => println!("part 2: {}", nearest),
+                Err(err) => fail(err),
+            }
+            return;
+        }
+        ["image"] | ["image", "--json"] => {
+            match seed_images(&almanac) {
+                Ok(images) if args.len() == 2 => print!("{}", images_to_json(&images)),
+                Ok(images) => print!("{}", format_images(&images)),
+                Err(err) => fail(err),
+            }
+            return;
+        }
+        ["validate"] => {
+            for problem in validate(&almanac) {
+                println!("{}", problem);
+            }
+            return;
+        }
+        _ => (),
+    }
+
+    if args.contains(&"--strict") {
+        let errors: Vec<Problem> = validate(&almanac)
+            .into_iter()
+            .filter(|problem| problem.issue.is_error())
+            .collect();
+
+        if !errors.is_empty() {
+            for problem in errors {
+                eprintln!("{}", problem);
+            }
+            process::exit(1);
+        }
+    }
+
+    let map = match compose_almanac(&almanac) {
+        Ok(map) => map,
+        Err(err) => fail(err),
+    };
+
+    let part_1_result = find_nearest_composed(&almanac, &map);
     println!("part 1: {}", part_1_result);
 
-    let part_2_result = part2(&almanac);
+    let part_2_result = find_nearest_range_composed(&almanac, &map);
     println!("part 2: {}", part_2_result);
 }
 
@@ -151,7 +992,7 @@ fn main() {
 mod tests {
     use super::*;
 
-    const SAMPLE_INPUT: &'static str = r"
+    const SAMPLE_INPUT: &str = r"
     seeds: 79 14 55 13
 
     seed-to-soil map:
@@ -189,15 +1030,255 @@ mod tests {
 
     #[test]
     fn part1() {
-        let almanac = parse_input(&SAMPLE_INPUT);
-        let result = find_nearest_for_planting(&almanac);
+        let almanac = parse_input(SAMPLE_INPUT);
+        let result = find_nearest_for_planting(&almanac).unwrap();
         assert_eq!(result, 35)
     }
 
     #[test]
     fn part2() {
-        let almanac = parse_input(&SAMPLE_INPUT);
-        let result = super::part2(&almanac);
+        let almanac = parse_input(SAMPLE_INPUT);
+        let result = super::part2(&almanac).unwrap();
         assert_eq!(result, 46)
     }
+
+    #[test]
+    fn composed() {
+        let almanac = parse_input(SAMPLE_INPUT);
+        let map = compose_almanac(&almanac).unwrap();
+
+        assert_eq!(find_nearest_composed(&almanac, &map), 35);
+        assert_eq!(find_nearest_range_composed(&almanac, &map), 46);
+    }
+
+    #[test]
+    fn composed_matches_each_stage() {
+        let almanac = parse_input(SAMPLE_INPUT);
+        let map = compose_almanac(&almanac).unwrap();
+        let stages = stage_maps(&almanac).unwrap();
+
+        for seed in 0..200 {
+            let location = stages.iter().fold(seed, |acc, stage| stage.lookup(acc));
+            assert_eq!(map.lookup(seed), location, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn categories() {
+        let almanac = parse_input(SAMPLE_INPUT);
+        assert_eq!(almanac.maps[0].source, "seed");
+        assert_eq!(almanac.maps[6].destination, "location");
+        assert_eq!(almanac.validate_chain(), Ok(()));
+
+        let output = query(&almanac, "soil", "humidity", &[81]).unwrap();
+        assert_eq!(
+            output,
+            "soil -> fertilizer -> water -> light -> temperature -> humidity\n81 -> 78\n"
+        );
+
+        assert!(matches!(
+            almanac.path("humidity", "soil"),
+            Err(CategoryError::NoPath { .. })
+        ));
+        assert_eq!(
+            almanac.path("seed", "fish").err(),
+            Some(CategoryError::Unknown("fish".to_string()))
+        );
+    }
+
+    #[test]
+    fn out_of_order_maps() {
+        let almanac = parse_input(
+            "
+            seeds: 1 5
+
+            soil-to-location map:
+            10 0 5
+
+            seed-to-soil map:
+            100 0 10
+            ",
+        );
+
+        assert_eq!(
+            almanac.validate_chain(),
+            Err(CategoryError::Broken {
+                destination: "location".to_string(),
+                next: "seed".to_string()
+            })
+        );
+
+        let map = compose_almanac(&almanac).unwrap();
+        assert_eq!(find_nearest_composed(&almanac, &map), 101);
+        assert_eq!(find_nearest_range_composed(&almanac, &map), 101);
+    }
+
+    #[test]
+    fn inverse() {
+        let almanac = parse_input(SAMPLE_INPUT);
+
+        let seeds = find_seeds_for(&almanac, Range::span(82, 83)).unwrap();
+        assert!(seeds.contains(&Range::span(79, 80)));
+
+        let map = compose_almanac(&almanac).unwrap();
+        let locations = Range::span(40, 60);
+        for seed in 0..200 {
+            let inside = find_seeds_for(&almanac, locations)
+                .unwrap()
+                .iter()
+                .any(|seeds| seeds.start <= seed && seed < seeds.end);
+            let location = map.lookup(seed);
+            assert_eq!(
+                inside,
+                locations.start <= location && location < locations.end
+            );
+        }
+    }
+
+    #[test]
+    fn reverse_search() {
+        let almanac = parse_input(SAMPLE_INPUT);
+        assert_eq!(find_nearest_range_reverse(&almanac).unwrap(), 46);
+    }
+
+    #[test]
+    fn validation() {
+        let almanac = parse_input(SAMPLE_INPUT);
+        assert!(validate(&almanac)
+            .iter()
+            .all(|problem| !problem.issue.is_error()));
+
+        let almanac = parse_input(
+            "seeds: 1 2
+            seed-to-location map:
+            50 10 10
+            60 15 10
+            0 40 0
+            0 9223372036854775800 10
+            0 30 5
+            ",
+        );
+        let issues: Vec<Issue> = validate(&almanac)
+            .into_iter()
+            .map(|problem| problem.issue)
+            .collect();
+
+        assert_eq!(
+            issues,
+            [
+                Issue::Overlap { line: 4, other: 3 },
+                Issue::ZeroLength { line: 5 },
+                Issue::Overflow { line: 6 },
+                Issue::Gap { start: 25, end: 30 },
+            ]
+        );
+        assert_eq!(
+            validate(&almanac)[0].to_string(),
+            "seed-to-location map: line 4 overlaps line 3"
+        );
+    }
+
+    // Small numbers so that ranges keep landing on each other's boundaries.
+    fn random_almanac(rng: &mut Rng) -> (String, Vec<(i64, i64)>) {
+        let categories = ["seed", "soil", "water", "location"];
+        let mut maps = String::new();
+
+        for pair in categories.windows(2) {
+            maps.push_str(&format!("{}-to-{} map:\n", pair[0], pair[1]));
+            for _ in 0..rng.below(5) {
+                let (dst, src, len) = (rng.below(60), rng.below(60), rng.below(20));
+                maps.push_str(&format!("{} {} {}\n", dst, src, len));
+            }
+        }
+
+        let seeds = (0..1 + rng.below(3))
+            .map(|_| (rng.below(60), 1 + rng.below(20)))
+            .collect();
+
+        (maps, seeds)
+    }
+
+    #[test]
+    fn point_and_interval_solvers_agree() {
+        let mut rng = Rng(0x5eed);
+
+        for _ in 0..500 {
+            let (maps, seeds) = random_almanac(&mut rng);
+
+            let ranges: Vec<String> = seeds
+                .iter()
+                .map(|(start, len)| format!("{} {}", start, len))
+                .collect();
+            let points: Vec<String> = seeds
+                .iter()
+                .flat_map(|&(start, len)| start..start + len)
+                .map(|seed| seed.to_string())
+                .collect();
+
+            let ranges = parse_input(&format!("seeds: {}\n{}", ranges.join(" "), maps));
+            let points = parse_input(&format!("seeds: {}\n{}", points.join(" "), maps));
+
+            let expected = find_nearest_for_planting(&points).unwrap();
+            let map = compose_almanac(&ranges).unwrap();
+
+            assert_eq!(super::part2(&ranges).unwrap(), expected, "{}", maps);
+            assert_eq!(find_nearest_range_composed(&ranges, &map), expected);
+            assert_eq!(find_nearest_range_reverse(&ranges).unwrap(), expected);
+            assert_eq!(find_nearest_composed(&points, &map), expected);
+        }
+    }
+
+    #[test]
+    fn parallel() {
+        let almanac = parse_input(SAMPLE_INPUT);
+        for threads in 1..4 {
+            assert_eq!(part2_parallel(&almanac, threads).unwrap(), 46);
+        }
+
+        let almanac = parse_input(&generate_almanac(&mut Rng(1), 50, 20));
+        assert_eq!(
+            part2_parallel(&almanac, 4).unwrap(),
+            super::part2(&almanac).unwrap()
+        );
+        assert_eq!(
+            find_nearest_range_composed(&almanac, &compose_almanac(&almanac).unwrap()),
+            super::part2(&almanac).unwrap()
+        );
+    }
+
+    #[test]
+    fn images() {
+        let almanac = parse_input(SAMPLE_INPUT);
+        let images = seed_images(&almanac).unwrap();
+
+        assert_eq!(images.len(), 8);
+        assert_eq!(
+            images[0].intervals,
+            [Range::span(55, 68), Range::span(79, 93)]
+        );
+        assert_eq!(images[0].coverage(), 27);
+        assert_eq!(images[1].unmapped, Some(0));
+        assert_eq!(images[7].category, "location");
+        assert_eq!(images[7].intervals[0].start, 46);
+
+        let broken = parse_input(&SAMPLE_INPUT.replace("light-to-temperature", "light-to-heat"));
+        assert!(matches!(
+            seed_images(&broken),
+            Err(CategoryError::NoPath { .. })
+        ));
+        assert!(matches!(
+            super::part2(&broken),
+            Err(CategoryError::NoPath { .. })
+        ));
+        assert!(part2_parallel(&broken, 2).is_err());
+        assert!(find_nearest_for_planting(&broken).is_err());
+        assert!(find_nearest_range_reverse(&broken).is_err());
+        assert!(find_seeds_for(&broken, Range::span(0, 1)).is_err());
+        assert_eq!(json_escape("say \"hi\"\\\n"), "say \\\"hi\\\"\\\\\\u000a");
+
+        let json = images_to_json(&images);
+        assert!(json.starts_with(
+            "[\n  {\"category\": \"seed\", \"intervals\": [[55, 68], [79, 93]], \"count\": 2, \"coverage\": 27, \"unmapped\": null},"
+        ));
+    }
 }
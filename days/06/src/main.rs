@@ -1,64 +1,646 @@
-type Input = (Vec<f64>, Vec<f64>);
+use std::cmp::Ordering;
+use std::env;
+use std::fmt;
+use std::process;
 
-fn parse_input(input: &str) -> Input {
-    input
-        .trim()
-        .split_once('\n')
-        .map(|(times_str, dists_str)| (parse_line(times_str), parse_line(dists_str)))
-        .unwrap()
+/// One race from the sheet. Numbers stay as digits so that a kerned race
+/// longer than a `u64` still parses.
+#[derive(Debug, Clone, PartialEq)]
+struct Race {
+    time: BigUint,
+    record: BigUint,
+}
+
+impl Race {
+    fn small(&self) -> Option<(u64, u64)> {
+        Some((self.time.to_u64()?, self.record.to_u64()?))
+    }
 }
 
-fn parse_line(line: &str) -> Vec<f64> {
-    line.split_once(":")
-        .map(|(_, nums_str)| {
-            nums_str
-                .trim()
-                .split_whitespace()
-                .map(|num_str| num_str.parse::<f64>().unwrap())
+/// How to read the columns: one race per column, or all the columns kerned
+/// together into a single race.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ParseMode {
+    Separate,
+    Kerned,
+}
+
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    Unlabelled { line: usize },
+    BadNumber { line: usize, token: String },
+    Repeated { label: &'static str, line: usize },
+    Missing { label: &'static str },
+    Columns { times: usize, records: usize },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Unlabelled { line } => write!(f, "line {} has no label", line),
+            ParseError::BadNumber { line, token } => {
+                write!(f, "line {}: {:?} is not a number", line, token)
+            }
+            ParseError::Repeated { label, line } => {
+                write!(f, "line {}: a second {} line", line, label)
+            }
+            ParseError::Missing { label } => write!(f, "no {} line", label),
+            ParseError::Columns { times, records } => {
+                write!(f, "{} times but {} distances", times, records)
+            }
+        }
+    }
+}
+
+// The Time and Distance lines may come in either order; any other labelled
+// line is skipped.
+fn parse_input(input: &str, mode: ParseMode) -> Result<Vec<Race>, ParseError> {
+    let mut times = None;
+    let mut records = None;
+
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (label, nums_str) = line
+            .split_once(':')
+            .ok_or(ParseError::Unlabelled { line: i + 1 })?;
+        let (label, slot) = match label.trim() {
+            "Time" => ("Time", &mut times),
+            "Distance" => ("Distance", &mut records),
+            _ => continue,
+        };
+        if slot.is_some() {
+            return Err(ParseError::Repeated { label, line: i + 1 });
+        }
+        *slot = Some(parse_line(nums_str, i + 1)?);
+    }
+
+    let times = times.ok_or(ParseError::Missing { label: "Time" })?;
+    let records = records.ok_or(ParseError::Missing { label: "Distance" })?;
+    if times.len() != records.len() {
+        return Err(ParseError::Columns {
+            times: times.len(),
+            records: records.len(),
+        });
+    }
+
+    Ok(match mode {
+        ParseMode::Separate => times
+            .iter()
+            .zip(&records)
+            .map(|(time, record)| Race {
+                time: BigUint::parse(time),
+                record: BigUint::parse(record),
+            })
+            .collect(),
+        ParseMode::Kerned => vec![Race {
+            time: BigUint::parse(&times.concat()),
+            record: BigUint::parse(&records.concat()),
+        }],
+    })
+}
+
+fn parse_line(nums_str: &str, line: usize) -> Result<Vec<&str>, ParseError> {
+    nums_str
+        .split_whitespace()
+        .map(|token| match token.chars().all(|c| c.is_ascii_digit()) {
+            true => Ok(token),
+            false => Err(ParseError::BadNumber {
+                line,
+                token: token.to_string(),
+            }),
         })
-        .unwrap()
         .collect()
 }
 
-fn concat_nums(nums: &Vec<f64>) -> f64 {
-    let concat_str: String = nums.iter().map(|n| n.to_string()).collect();
-    concat_str.parse().unwrap()
+/// An arbitrary-precision unsigned integer, for races too long for `u64`.
+/// Only has the handful of operations the race solver needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BigUint {
+    // Little-endian, with no trailing zero limbs.
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    fn new(mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        BigUint { limbs }
+    }
+
+    fn parse(digits: &str) -> Self {
+        digits.chars().fold(BigUint::from(0), |acc, c| {
+            let digit = c.to_digit(10).unwrap() as u128;
+            acc.mul(&BigUint::from(10)).add(&BigUint::from(digit))
+        })
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        match self.limbs[..] {
+            [] => Some(0),
+            [lo] => Some(lo as u64),
+            [lo, hi] => Some((hi as u64) << 32 | lo as u64),
+            _ => None,
+        }
+    }
+
+    fn bits(&self) -> usize {
+        self.limbs.last().map_or(0, |&top| {
+            self.limbs.len() * 32 - top.leading_zeros() as usize
+        })
+    }
+
+    fn add(&self, other: &BigUint) -> BigUint {
+        let mut limbs = Vec::with_capacity(self.limbs.len().max(other.limbs.len()) + 1);
+        let mut carry = 0_u64;
+
+        for i in 0..self.limbs.len().max(other.limbs.len()) {
+            let sum = carry
+                + *self.limbs.get(i).unwrap_or(&0) as u64
+                + *other.limbs.get(i).unwrap_or(&0) as u64;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        limbs.push(carry as u32);
+
+        BigUint::new(limbs)
+    }
+
+    /// Panics if `other` is bigger than `self`.
+    fn sub(&self, other: &BigUint) -> BigUint {
+        assert!(*self >= *other, "subtraction underflowed");
+        let mut limbs = Vec::with_capacity(self.limbs.len());
+        let mut borrow = 0_i64;
+
+        for (i, &limb) in self.limbs.iter().enumerate() {
+            let mut diff = limb as i64 - *other.limbs.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = 0;
+            if diff < 0 {
+                diff += 1 << 32;
+                borrow = 1;
+            }
+            limbs.push(diff as u32);
+        }
+
+        BigUint::new(limbs)
+    }
+
+    fn mul(&self, other: &BigUint) -> BigUint {
+        let mut limbs = vec![0_u32; self.limbs.len() + other.limbs.len()];
+
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0_u64;
+            for (j, &b) in other.limbs.iter().enumerate() {
+                let product = a as u64 * b as u64 + limbs[i + j] as u64 + carry;
+                limbs[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            limbs[i + other.limbs.len()] = carry as u32;
+        }
+
+        BigUint::new(limbs)
+    }
+
+    fn shl(&self, bits: usize) -> BigUint {
+        let (words, bits) = (bits / 32, bits % 32);
+        let mut limbs = vec![0_u32; words];
+        let mut carry = 0_u32;
+
+        for &limb in &self.limbs {
+            limbs.push(((limb as u64) << bits) as u32 | carry);
+            carry = ((limb as u64) << bits >> 32) as u32;
+        }
+        limbs.push(carry);
+
+        BigUint::new(limbs)
+    }
+
+    fn shr(&self, bits: usize) -> BigUint {
+        let (words, bits) = (bits / 32, bits % 32);
+        let limbs = (words..self.limbs.len())
+            .map(|i| {
+                let hi = *self.limbs.get(i + 1).unwrap_or(&0) as u64;
+                ((hi << 32 | self.limbs[i] as u64) >> bits) as u32
+            })
+            .collect();
+
+        BigUint::new(limbs)
+    }
+
+    // Schoolbook long division, one limb at a time from the top.
+    fn div_rem_small(&self, divisor: u64) -> (BigUint, u64) {
+        let mut remainder = 0_u128;
+        let mut limbs: Vec<u32> = self
+            .limbs
+            .iter()
+            .rev()
+            .map(|&limb| {
+                let acc = remainder << 32 | limb as u128;
+                remainder = acc % divisor as u128;
+                (acc / divisor as u128) as u32
+            })
+            .collect();
+        limbs.reverse();
+
+        (BigUint::new(limbs), remainder as u64)
+    }
+
+    // Digit-by-digit square root in base 2, which only needs shifts,
+    // additions and comparisons.
+    fn isqrt(&self) -> BigUint {
+        let mut remainder = self.clone();
+        let mut root = BigUint::from(0);
+        let mut bit = BigUint::from(1).shl(self.bits() / 2 * 2);
+
+        while !bit.limbs.is_empty() {
+            let candidate = root.add(&bit);
+            if remainder >= candidate {
+                remainder = remainder.sub(&candidate);
+                root = root.shr(1).add(&bit);
+            } else {
+                root = root.shr(1);
+            }
+            bit = bit.shr(2);
+        }
+
+        root
+    }
+}
+
+impl From<u128> for BigUint {
+    fn from(n: u128) -> Self {
+        BigUint::new(vec![
+            n as u32,
+            (n >> 32) as u32,
+            (n >> 64) as u32,
+            (n >> 96) as u32,
+        ])
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut digits = Vec::new();
+        let mut n = self.clone();
+
+        // Peels off one decimal digit at a time.
+        while !n.limbs.is_empty() {
+            let (quotient, remainder) = n.div_rem_small(10);
+            digits.push(char::from(b'0' + remainder as u8));
+            n = quotient;
+        }
+
+        if digits.is_empty() {
+            digits.push('0');
+        }
+        write!(f, "{}", digits.iter().rev().collect::<String>())
+    }
+}
+
+// Holding for `h` ms travels `h * (t - h)` mm, which beats the record when
+// `h^2 - t*h + d < 0`. The roots of that quadratic are found with an exact
+// integer square root and then nudged onto the first winning hold time, so a
+// hold time that only ties the record is never counted.
+fn calc_num_record_times(t: u64, d: u64) -> u64 {
+    let (t, d) = (t as u128, d as u128);
+    let wins = |h: u128| h * (t - h) > d;
+
+    // The best hold time is half the race. If that can't win, nothing can.
+    if !wins(t / 2) {
+        return 0;
+    }
+
+    let root = (t * t - 4 * d).isqrt();
+    let mut lo = (t - root) / 2;
+    while !wins(lo) {
+        lo += 1;
+    }
+    while lo > 0 && wins(lo - 1) {
+        lo -= 1;
+    }
+
+    (t - 2 * lo + 1) as u64
+}
+
+fn calc_num_record_times_big(t: &BigUint, d: &BigUint) -> BigUint {
+    let one = BigUint::from(1);
+    let wins = |h: &BigUint| h.mul(&t.sub(h)) > *d;
+
+    if !wins(&t.shr(1)) {
+        return BigUint::from(0);
+    }
+
+    let root = t.mul(t).sub(&d.shl(2)).isqrt();
+    let mut lo = t.sub(&root).shr(1);
+    while !wins(&lo) {
+        lo = lo.add(&one);
+    }
+    while !lo.limbs.is_empty() && wins(&lo.sub(&one)) {
+        lo = lo.sub(&one);
+    }
+
+    t.add(&one).sub(&lo.shl(1))
+}
+
+/// How far a boat gets in a race of `time` ms after holding the button for
+/// `hold` ms. Models are expected to be unimodal in `hold`: distance rises to a
+/// single peak and then falls, which is what the generic solver relies on.
+trait BoatModel {
+    fn distance(&self, hold: u64, time: u64) -> u128;
+
+    /// The number of winning hold times, if the model has a closed form.
+    fn closed_form(&self, _time: u64, _record: u64) -> Option<u64> {
+        None
+    }
+
+    /// The same for races too long for `u64`.
+    fn closed_form_big(&self, _time: &BigUint, _record: &BigUint) -> Option<BigUint> {
+        None
+    }
+}
+
+/// Speed goes up by `rate` mm/ms for every ms the button is held. The puzzle's
+/// boat is `LinearCharge { rate: 1 }`.
+struct LinearCharge {
+    rate: u64,
+}
+
+impl BoatModel for LinearCharge {
+    fn distance(&self, hold: u64, time: u64) -> u128 {
+        self.rate as u128 * hold as u128 * (time - hold) as u128
+    }
+
+    // `rate * x > d` exactly when `x > d / rate` for whole numbers.
+    fn closed_form(&self, time: u64, record: u64) -> Option<u64> {
+        Some(calc_num_record_times(time, record / self.rate))
+    }
+
+    fn closed_form_big(&self, time: &BigUint, record: &BigUint) -> Option<BigUint> {
+        let (record, _) = record.div_rem_small(self.rate);
+        Some(calc_num_record_times_big(time, &record))
+    }
+}
+
+/// Like `LinearCharge`, but the boat can't go faster than `max_speed`.
+struct SpeedCap {
+    rate: u64,
+    max_speed: u64,
+}
+
+impl BoatModel for SpeedCap {
+    fn distance(&self, hold: u64, time: u64) -> u128 {
+        let speed = (self.rate as u128 * hold as u128).min(self.max_speed as u128);
+        speed * (time - hold) as u128
+    }
+}
+
+/// Speed grows with the square of the hold time.
+struct QuadraticCharge {
+    rate: u64,
+}
+
+impl BoatModel for QuadraticCharge {
+    fn distance(&self, hold: u64, time: u64) -> u128 {
+        self.rate as u128 * hold as u128 * hold as u128 * (time - hold) as u128
+    }
+}
+
+/// Like `LinearCharge`, but the boat loses `drag` mm/ms of speed every ms it
+/// moves, until it stops. With no drag it's just `LinearCharge`.
+struct Drag {
+    rate: u64,
+    drag: u64,
+}
+
+impl BoatModel for Drag {
+    fn distance(&self, hold: u64, time: u64) -> u128 {
+        let speed = self.rate as u128 * hold as u128;
+        let drag = self.drag as u128;
+        if drag == 0 {
+            return speed * (time - hold) as u128;
+        }
+
+        let moving = speed.div_ceil(drag).min((time - hold) as u128);
+
+        moving * speed - drag * moving * moving.saturating_sub(1) / 2
+    }
+}
+
+// First `h` in `lo..=hi` where `pred` holds, for a `pred` that is false and
+// then true. Returns `hi + 1` if it never holds.
+fn bisect(mut lo: u64, mut hi: u64, pred: impl Fn(u64) -> bool) -> u64 {
+    let end = hi + 1;
+    hi = end;
+
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if mid < end && pred(mid) {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    lo
+}
+
+/// The first and last hold times that beat `record`, found by bisection.
+fn winning_holds(model: &dyn BoatModel, time: u64, record: u64) -> Option<(u64, u64)> {
+    let record = record as u128;
+    let distance = |hold| model.distance(hold, time);
+
+    // The peak is the first hold time where holding any longer stops helping.
+    let peak = match time {
+        0 => 0,
+        _ => bisect(0, time - 1, |h| distance(h) >= distance(h + 1)),
+    };
+    if distance(peak) <= record {
+        return None;
+    }
+
+    let first = bisect(0, peak, |h| distance(h) > record);
+    let last = bisect(peak, time, |h| distance(h) <= record) - 1;
+
+    Some((first, last))
+}
+
+fn count_winning_holds(model: &dyn BoatModel, time: u64, record: u64) -> u64 {
+    model.closed_form(time, record).unwrap_or_else(|| {
+        winning_holds(model, time, record).map_or(0, |(first, last)| last - first + 1)
+    })
+}
+
+// Races too long for `u64` can only be counted by models with a big closed
+// form, so this is `None` for the rest.
+fn count_race(model: &dyn BoatModel, race: &Race) -> Option<BigUint> {
+    match race.small() {
+        Some((t, d)) => Some(BigUint::from(count_winning_holds(model, t, d) as u128)),
+        None => model.closed_form_big(&race.time, &race.record),
+    }
+}
+
+fn find_num_ways_to_win_multiple_races(races: &[Race], model: &dyn BoatModel) -> Option<BigUint> {
+    races.iter().try_fold(BigUint::from(1), |total, race| {
+        Some(total.mul(&count_race(model, race)?))
+    })
 }
 
-fn calc_num_record_times(t: f64, d: f64) -> f64 {
-    let t = -t;
-    let m1 = ((2.0 * d) / (-t - (t.powi(2) - 4.0 * d).sqrt())).ceil();
-    let m2 = ((2.0 * d) / (-t + (t.powi(2) - 4.0 * d).sqrt())).floor() + 1.0;
-    m1 - m2
+fn find_num_ways_to_win_one_big_race(race: &Race, model: &dyn BoatModel) -> Option<BigUint> {
+    count_race(model, race)
 }
 
-fn find_num_ways_to_win_multiple_races(input: &Input) -> f64 {
-    let (times, dists) = input;
-    let mut total = 1.0;
+const PLOT_ROWS: u64 = 40;
+const PLOT_WIDTH: u128 = 50;
 
-    for i in 0..input.0.len() {
-        let t = times[i];
-        let d = dists[i];
-        total *= calc_num_record_times(t, d);
+// Long races are sampled down to about `max_rows` hold times, always keeping
+// the first and last winning ones so the edges of the answer are visible.
+fn plot_race(model: &dyn BoatModel, time: u64, record: u64, max_rows: u64) -> String {
+    let winning = winning_holds(model, time, record);
+
+    let step = (time / max_rows.max(1)).max(1);
+    let mut holds: Vec<u64> = (0..=time).step_by(step as usize).collect();
+    holds.extend([time / 2, time]);
+    if let Some((first, last)) = winning {
+        holds.extend([first.saturating_sub(1), first, last, last + 1]);
+    }
+    holds.retain(|&hold| hold <= time);
+    holds.sort_unstable();
+    holds.dedup();
+
+    let distances: Vec<u128> = holds
+        .iter()
+        .map(|&hold| model.distance(hold, time))
+        .collect();
+    let max = distances
+        .iter()
+        .copied()
+        .chain([record as u128])
+        .max()
+        .unwrap()
+        .max(1);
+    let record_col = (record as u128 * PLOT_WIDTH / max) as usize;
+
+    let mut output = format!("time {} ms, record {} mm\n", time, record);
+    output.push_str(&format!("{:>12} {:>16}\n", "hold", "distance"));
+
+    for (&hold, &distance) in holds.iter().zip(&distances) {
+        let bar_len = (distance * PLOT_WIDTH / max) as usize;
+        let bar: String = (0..=PLOT_WIDTH as usize)
+            .map(|col| match col {
+                _ if col == record_col => '|',
+                _ if col < bar_len => '#',
+                _ => ' ',
+            })
+            .collect();
+        let marker = if distance > record as u128 { '*' } else { ' ' };
+
+        output.push_str(&format!(
+            "{:>12} {:>16} {} {}\n",
+            hold,
+            distance,
+            marker,
+            bar.trim_end()
+        ));
+    }
+
+    match winning {
+        Some((first, last)) => output.push_str(&format!(
+            "winning holds: {}..={} ({} ways)\n",
+            first,
+            last,
+            last - first + 1
+        )),
+        None => output.push_str("no hold time beats the record\n"),
     }
 
-    total
+    output
 }
 
-fn find_num_ways_to_win_one_big_race(input: &Input) -> f64 {
-    let t = concat_nums(&input.0);
-    let d = concat_nums(&input.1);
-    calc_num_record_times(t, d)
+fn parse_model(arg: &str) -> Box<dyn BoatModel> {
+    let mut parts = arg.split(':');
+    let name = parts.next().unwrap();
+    let mut param = || parts.next().map_or(1, |p| p.parse().unwrap());
+
+    match name {
+        // The closed form divides the record by the rate.
+        "linear" => match param() {
+            0 => panic!("Unexpected boat model: {}", arg),
+            rate => Box::new(LinearCharge { rate }),
+        },
+        "cap" => Box::new(SpeedCap {
+            rate: param(),
+            max_speed: param(),
+        }),
+        "quadratic" => Box::new(QuadraticCharge { rate: param() }),
+        "drag" => Box::new(Drag {
+            rate: param(),
+            drag: param(),
+        }),
+        other => panic!("Unexpected boat model: {}", other),
+    }
 }
 
 fn main() {
     let input = include_str!("../input.txt");
-    let input = parse_input(input);
+    let parse = |mode| match parse_input(input, mode) {
+        Ok(races) => races,
+        Err(err) => {
+            eprintln!("Invalid race sheet: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let model = env::args()
+        .skip(1)
+        .find_map(|arg| arg.strip_prefix("--model=").map(parse_model))
+        .unwrap_or(Box::new(LinearCharge { rate: 1 }));
 
-    let part1_result = find_num_ways_to_win_multiple_races(&input);
+    if env::args().nth(1).as_deref() == Some("plot") {
+        for (i, race) in parse(ParseMode::Separate).iter().enumerate() {
+            match race.small() {
+                Some((t, d)) => println!(
+                    "Race {}: {}",
+                    i + 1,
+                    plot_race(model.as_ref(), t, d, PLOT_ROWS)
+                ),
+                None => println!("Race {}: too long to plot", i + 1),
+            }
+        }
+        return;
+    }
+
+    let solved = |result: Option<BigUint>| {
+        result.unwrap_or_else(|| {
+            eprintln!("This boat model can't handle races that long");
+            process::exit(1);
+        })
+    };
+
+    let races = parse(ParseMode::Separate);
+    let part1_result = solved(find_num_ways_to_win_multiple_races(&races, model.as_ref()));
     println!("Part 1: {}", part1_result);
 
-    let part2_result = find_num_ways_to_win_one_big_race(&input);
+    let race = &parse(ParseMode::Kerned)[0];
+    let part2_result = solved(find_num_ways_to_win_one_big_race(race, model.as_ref()));
     println!("Part 2: {}", part2_result);
 }
 
@@ -66,22 +648,200 @@ fn main() {
 mod tests {
     use super::*;
 
-    const SAMPLE_DATA: &'static str = r"
+    const SAMPLE_DATA: &str = r"
     Time:      7  15   30
     Distance:  9  40  200
     ";
 
     #[test]
     fn it_should_find_the_num_ways_to_win_multiple_races() {
-        let input = parse_input(&SAMPLE_DATA);
-        let result = find_num_ways_to_win_multiple_races(&input);
-        assert_eq!(result, 288.0);
+        let races = parse_input(SAMPLE_DATA, ParseMode::Separate).unwrap();
+        let result = find_num_ways_to_win_multiple_races(&races, &LinearCharge { rate: 1 });
+        assert_eq!(result, Some(BigUint::from(288)));
     }
 
     #[test]
     fn it_should_find_the_num_ways_to_win_one_big_race() {
-        let input = parse_input(&SAMPLE_DATA);
-        let result = find_num_ways_to_win_one_big_race(&input);
-        assert_eq!(result, 71503.0)
+        let races = parse_input(SAMPLE_DATA, ParseMode::Kerned).unwrap();
+        let result = find_num_ways_to_win_one_big_race(&races[0], &LinearCharge { rate: 1 });
+        assert_eq!(result, Some(BigUint::from(71503)))
+    }
+
+    #[test]
+    fn it_should_parse_labelled_lines_in_any_order() {
+        let sheet = "Distance: 9 40\nWeather: sunny\nTime: 7 15\n";
+        let races = parse_input(sheet, ParseMode::Separate).unwrap();
+        assert_eq!(races.len(), 2);
+        assert_eq!(races[1].small(), Some((15, 40)));
+
+        let race = &parse_input(sheet, ParseMode::Kerned).unwrap()[0];
+        assert_eq!(race.small(), Some((715, 940)));
+    }
+
+    #[test]
+    fn it_should_reject_malformed_sheets() {
+        let parse = |sheet| parse_input(sheet, ParseMode::Separate);
+
+        assert_eq!(
+            parse("Time: 7 15\nDistance: 9"),
+            Err(ParseError::Columns {
+                times: 2,
+                records: 1
+            })
+        );
+        assert_eq!(
+            parse("Time: 7\nDistance: 9\nTime: 8"),
+            Err(ParseError::Repeated {
+                label: "Time",
+                line: 3
+            })
+        );
+        assert_eq!(
+            parse("Time: 7"),
+            Err(ParseError::Missing { label: "Distance" })
+        );
+        assert_eq!(parse("Time: 7\n9"), Err(ParseError::Unlabelled { line: 2 }));
+        assert_eq!(
+            parse("Time: 7\nDistance: -9"),
+            Err(ParseError::BadNumber {
+                line: 2,
+                token: "-9".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn it_should_not_count_a_tied_record() {
+        // Holding for 5 ms ties a record of 25 mm exactly.
+        assert_eq!(calc_num_record_times(10, 25), 0);
+        assert_eq!(calc_num_record_times(10, 24), 1);
+        assert_eq!(calc_num_record_times(30, 200), 9);
+        assert_eq!(calc_num_record_times(30, 199), 11);
+    }
+
+    #[test]
+    fn it_should_match_a_brute_force_count() {
+        for t in 0..60_u64 {
+            for d in 0..t * t / 4 + 2 {
+                let expected = (0..=t).filter(|h| h * (t - h) > d).count() as u64;
+                assert_eq!(calc_num_record_times(t, d), expected, "t={} d={}", t, d);
+                assert_eq!(
+                    calc_num_record_times_big(&BigUint::from(t as u128), &BigUint::from(d as u128)),
+                    BigUint::from(expected as u128)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_stay_exact_for_huge_races() {
+        // t^2 and 4d are around 2^66 here, where an f64 can't tell them apart.
+        let k = u32::MAX as u64;
+        assert_eq!(calc_num_record_times(2 * k, k * k), 0);
+        assert_eq!(calc_num_record_times(2 * k, k * k - 1), 1);
+
+        let t = BigUint::parse(&format!("1{}", "0".repeat(30)));
+        let best = BigUint::parse(&format!("25{}", "0".repeat(58)));
+        let one = BigUint::from(1);
+        assert_eq!(calc_num_record_times_big(&t, &best), BigUint::from(0));
+        assert_eq!(calc_num_record_times_big(&t, &best.sub(&one)), one);
+        assert_eq!(best.to_string(), format!("25{}", "0".repeat(58)));
+    }
+
+    #[test]
+    fn it_should_divide_by_a_small_number() {
+        let n = BigUint::parse(&format!("7{}", "0".repeat(30)));
+        let (quotient, remainder) = n.add(&BigUint::from(5)).div_rem_small(u64::MAX);
+        assert_eq!(quotient.to_string(), "379470760369");
+        assert_eq!(remainder, 17091866197898054070);
+        assert_eq!(BigUint::from(0).div_rem_small(3), (BigUint::from(0), 0));
+    }
+
+    #[test]
+    fn it_should_count_huge_races_only_with_a_big_closed_form() {
+        let time = BigUint::parse(&format!("1{}", "0".repeat(30)));
+        let best = BigUint::parse(&format!("25{}", "0".repeat(58)));
+        let race = Race {
+            time,
+            record: best.sub(&BigUint::from(1)).mul(&BigUint::from(3)),
+        };
+
+        assert_eq!(
+            count_race(&LinearCharge { rate: 3 }, &race),
+            Some(BigUint::from(1))
+        );
+        assert_eq!(count_race(&QuadraticCharge { rate: 1 }, &race), None);
+    }
+
+    #[test]
+    fn it_should_solve_every_boat_model_by_bisection() {
+        let models: Vec<Box<dyn BoatModel>> = vec![
+            Box::new(LinearCharge { rate: 1 }),
+            Box::new(LinearCharge { rate: 3 }),
+            Box::new(SpeedCap {
+                rate: 2,
+                max_speed: 15,
+            }),
+            Box::new(QuadraticCharge { rate: 1 }),
+            Box::new(Drag { rate: 2, drag: 3 }),
+            Box::new(Drag { rate: 2, drag: 0 }),
+        ];
+
+        for model in &models {
+            for t in 0..40 {
+                for d in (0..2000).step_by(7) {
+                    let expected = (0..=t)
+                        .filter(|&h| model.distance(h, t) > d as u128)
+                        .count() as u64;
+                    let found = winning_holds(model.as_ref(), t, d)
+                        .map_or(0, |(first, last)| last - first + 1);
+
+                    assert_eq!(found, expected, "t={} d={}", t, d);
+                    assert_eq!(count_winning_holds(model.as_ref(), t, d), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_treat_no_drag_as_linear_charge() {
+        let drag = Drag { rate: 1, drag: 0 };
+        let linear = LinearCharge { rate: 1 };
+
+        assert_eq!(drag.distance(3, 10), 21);
+        for hold in 0..=10 {
+            assert_eq!(drag.distance(hold, 10), linear.distance(hold, 10));
+        }
+    }
+
+    #[test]
+    fn it_should_find_the_winning_interval() {
+        assert_eq!(
+            winning_holds(&LinearCharge { rate: 1 }, 30, 200),
+            Some((11, 19))
+        );
+        assert_eq!(winning_holds(&LinearCharge { rate: 1 }, 10, 25), None);
+    }
+
+    #[test]
+    fn it_should_plot_a_race() {
+        let plot = plot_race(&LinearCharge { rate: 1 }, 7, 9, PLOT_ROWS);
+        let lines: Vec<&str> = plot.lines().collect();
+
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[0], "time 7 ms, record 9 mm");
+        assert!(lines[2].ends_with('|') && !lines[2].contains('#'));
+        assert!(lines[5].starts_with("           3               12 * ####"));
+        assert_eq!(lines[10], "winning holds: 2..=5 (4 ways)");
+    }
+
+    #[test]
+    fn it_should_sample_long_races() {
+        let plot = plot_race(&LinearCharge { rate: 1 }, 71530, 940200, 10);
+
+        assert!(plot.lines().count() < 25);
+        assert!(plot.contains("          14 "));
+        assert!(plot.contains("       71516 "));
+        assert!(plot.ends_with("winning holds: 14..=71516 (71503 ways)\n"));
     }
 }
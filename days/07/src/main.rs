@@ -1,76 +1,608 @@
-struct Hand {
-    cards: Vec<char>,
+use std::cmp::Reverse;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::process;
+
+/// A Camel Cards label. Declared weakest first in the usual order, though a
+/// ruleset's deck decides how they actually compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Card {
+    Two,
+    Three,
+    Four,
+    Five,
+    Six,
+    Seven,
+    Eight,
+    Nine,
+    Ten,
+    Jack,
+    Queen,
+    King,
+    Ace,
+}
+
+const LABELS: &str = "23456789TJQKA";
+
+impl Card {
+    const ALL: [Card; 13] = [
+        Card::Two,
+        Card::Three,
+        Card::Four,
+        Card::Five,
+        Card::Six,
+        Card::Seven,
+        Card::Eight,
+        Card::Nine,
+        Card::Ten,
+        Card::Jack,
+        Card::Queen,
+        Card::King,
+        Card::Ace,
+    ];
+
+    fn from_label(label: char) -> Option<Card> {
+        LABELS.find(label).map(|i| Card::ALL[i])
+    }
+
+    fn label(self) -> char {
+        LABELS.as_bytes()[self as usize] as char
+    }
+}
+
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
+struct Hand<C = Card> {
+    cards: Vec<C>,
     bid: u32,
+    // Where the hand is in the input file, from 1.
+    line: usize,
+}
+
+impl<C: fmt::Display> Hand<C> {
+    fn label(&self) -> String {
+        self.cards.iter().map(|card| card.to_string()).collect()
+    }
+}
+
+const HAND_SIZE: usize = 5;
+
+#[derive(Debug, PartialEq)]
+enum HandError {
+    MissingBid {
+        line: usize,
+    },
+    BadBid {
+        line: usize,
+        bid: String,
+    },
+    InvalidCard {
+        line: usize,
+        card: String,
+    },
+    RepeatedCard {
+        line: usize,
+        card: String,
+    },
+    WrongLength {
+        line: usize,
+        found: usize,
+        expected: usize,
+    },
+}
+
+impl fmt::Display for HandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandError::MissingBid { line } => write!(f, "line {}: no bid", line),
+            HandError::BadBid { line, bid } => {
+                write!(f, "line {}: bid {:?} is not a number", line, bid)
+            }
+            HandError::InvalidCard { line, card } => {
+                write!(f, "line {}: {:?} is not a card", line, card)
+            }
+            HandError::RepeatedCard { line, card } => {
+                write!(f, "line {}: {} is dealt twice", line, card)
+            }
+            HandError::WrongLength {
+                line,
+                found,
+                expected,
+            } => write!(f, "line {}: {} cards instead of {}", line, found, expected),
+        }
+    }
+}
+
+/// Turns the cards half of a line into cards.
+trait CardReader {
+    type Card;
+
+    fn hand_size(&self) -> usize;
+
+    // On failure, returns the text that isn't a card.
+    fn read(&self, cards: &str) -> Result<Vec<Self::Card>, String>;
+
+    // A card that can't appear twice in one hand but does.
+    fn repeated(&self, _cards: &[Self::Card]) -> Option<String> {
+        None
+    }
+}
+
+/// Camel Cards hands: one character per card, plus any aliases such as `10`
+/// for a ten.
+struct HandFormat {
+    size: usize,
+    aliases: Vec<(String, Card)>,
+}
+
+impl HandFormat {
+    fn new(size: usize) -> Self {
+        HandFormat {
+            size,
+            aliases: Vec::new(),
+        }
+    }
 }
 
-fn parse_input(input: &str) -> Vec<Hand> {
-    input
-        .lines()
-        .map(|line| line.trim())
-        .filter(|line| !line.is_empty())
-        .map(|line| {
-            let (cards_str, bid_str) = line.split_once(" ").unwrap();
-            let cards: Vec<char> = cards_str.chars().collect();
-            let bid = bid_str.parse().unwrap();
-            Hand { cards, bid }
+impl CardReader for HandFormat {
+    type Card = Card;
+
+    fn hand_size(&self) -> usize {
+        self.size
+    }
+
+    // The longest matching alias is tried before the plain label.
+    fn read(&self, cards: &str) -> Result<Vec<Card>, String> {
+        let mut read = Vec::new();
+        let mut rest = cards;
+
+        while let Some(label) = rest.chars().next() {
+            let alias = self
+                .aliases
+                .iter()
+                .filter(|(alias, _)| rest.starts_with(alias.as_str()))
+                .max_by_key(|(alias, _)| alias.len());
+
+            let (card, len) = match alias {
+                Some((alias, card)) => (*card, alias.len()),
+                None => match Card::from_label(label) {
+                    Some(card) => (card, label.len_utf8()),
+                    None => return Err(label.to_string()),
+                },
+            };
+            read.push(card);
+            rest = &rest[len..];
+        }
+
+        Ok(read)
+    }
+}
+
+// Written as `<alias>=<label>`, like `10=T`.
+fn parse_alias(arg: &str) -> (String, Card) {
+    let (alias, label) = arg.split_once('=').unwrap_or((arg, ""));
+    match (alias, label.parse().ok().and_then(Card::from_label)) {
+        ("", _) | (_, None) => panic!("Unexpected alias: {}", arg),
+        (alias, Some(card)) => (alias.to_string(), card),
+    }
+}
+
+fn parse_input<R: CardReader>(input: &str, reader: &R) -> Result<Vec<Hand<R::Card>>, HandError> {
+    let mut hands = Vec::new();
+
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (cards_str, bid_str) = line
+            .split_once(' ')
+            .ok_or(HandError::MissingBid { line: i + 1 })?;
+        let cards = reader
+            .read(cards_str)
+            .map_err(|card| HandError::InvalidCard { line: i + 1, card })?;
+        if let Some(card) = reader.repeated(&cards) {
+            return Err(HandError::RepeatedCard { line: i + 1, card });
+        }
+        if cards.len() != reader.hand_size() {
+            return Err(HandError::WrongLength {
+                line: i + 1,
+                found: cards.len(),
+                expected: reader.hand_size(),
+            });
+        }
+        let bid = bid_str.trim().parse().map_err(|_| HandError::BadBid {
+            line: i + 1,
+            bid: bid_str.trim().to_string(),
+        })?;
+
+        hands.push(Hand {
+            cards,
+            bid,
+            line: i + 1,
+        });
+    }
+
+    Ok(hands)
+}
+
+/// A hand category such as "full house": the group sizes a hand needs, largest
+/// first. A hand has the strongest category whose every group it can cover.
+#[derive(Debug, Clone, PartialEq)]
+struct Category {
+    name: String,
+    shape: Vec<u32>,
+}
+
+impl Category {
+    fn matches(&self, counts: &[u32]) -> bool {
+        self.shape
+            .iter()
+            .enumerate()
+            .all(|(i, &size)| counts.get(i).copied().unwrap_or(0) >= size)
+    }
+}
+
+/// The rules of one Camel Cards variant.
+#[derive(Debug, Clone, PartialEq)]
+struct Ruleset {
+    name: String,
+    // Weakest card first.
+    deck: Vec<Card>,
+    wild: Vec<Card>,
+    // Weakest category first.
+    categories: Vec<Category>,
+}
+
+#[derive(Debug, PartialEq)]
+enum RulesetError {
+    Malformed { line: usize },
+    UnknownKey { line: usize, key: String },
+    UnknownCard { line: usize, card: char },
+    RepeatedCard { card: Card },
+    UnknownWild { card: Card },
+    MissingDeck,
+    NoCategories,
+    NotInDeck { card: Card },
+    WeakestNotUniversal { name: String },
+}
+
+impl fmt::Display for RulesetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RulesetError::Malformed { line } => write!(f, "line {} is not `key: value`", line),
+            RulesetError::UnknownKey { line, key } => {
+                write!(f, "line {}: unknown key {:?}", line, key)
+            }
+            RulesetError::UnknownCard { line, card } => {
+                write!(f, "line {}: {:?} is not a card", line, card)
+            }
+            RulesetError::RepeatedCard { card } => write!(f, "{} is in the deck twice", card),
+            RulesetError::UnknownWild { card } => {
+                write!(f, "wild card {} is not in the deck", card)
+            }
+            RulesetError::MissingDeck => write!(f, "no deck"),
+            RulesetError::NoCategories => write!(f, "no hand categories"),
+            RulesetError::NotInDeck { card } => write!(f, "{} isn't in the deck", card),
+            RulesetError::WeakestNotUniversal { name } => {
+                write!(f, "the weakest category {:?} doesn't fit every hand", name)
+            }
+        }
+    }
+}
+
+const STANDARD_RULES: &str = "
+name: standard
+deck: 23456789TJQKA
+category: high card = 1
+category: one pair = 2
+category: two pair = 2 2
+category: three of a kind = 3
+category: full house = 3 2
+category: four of a kind = 4
+category: five of a kind = 5
+";
+
+const JOKER_RULES: &str = "
+name: jokers
+deck: J23456789TQKA
+wild: J
+category: high card = 1
+category: one pair = 2
+category: two pair = 2 2
+category: three of a kind = 3
+category: full house = 3 2
+category: four of a kind = 4
+category: five of a kind = 5
+";
+
+impl Ruleset {
+    fn standard() -> Self {
+        Ruleset::parse(STANDARD_RULES).unwrap()
+    }
+
+    fn jokers() -> Self {
+        Ruleset::parse(JOKER_RULES).unwrap()
+    }
+
+    // One `key: value` per line. Categories are listed weakest first, each as
+    // `category: <name> = <group sizes>`.
+    fn parse(text: &str) -> Result<Self, RulesetError> {
+        let mut name = String::new();
+        let mut deck = None;
+        let mut wild = Vec::new();
+        let mut categories = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let malformed = RulesetError::Malformed { line: i + 1 };
+            let (key, value) = line.split_once(':').ok_or(malformed)?;
+            let value = value.trim();
+            match key.trim() {
+                "name" => name = value.to_string(),
+                "deck" => deck = Some(parse_cards(value, i + 1)?),
+                "wild" => wild = parse_cards(value, i + 1)?,
+                "category" => {
+                    let (category, shape) = value
+                        .split_once('=')
+                        .ok_or(RulesetError::Malformed { line: i + 1 })?;
+                    let shape = shape
+                        .split_whitespace()
+                        .map(|size| size.parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| RulesetError::Malformed { line: i + 1 })?;
+                    categories.push(Category {
+                        name: category.trim().to_string(),
+                        shape,
+                    });
+                }
+                other => {
+                    return Err(RulesetError::UnknownKey {
+                        line: i + 1,
+                        key: other.to_string(),
+                    })
+                }
+            }
+        }
+
+        let deck = deck
+            .filter(|deck| !deck.is_empty())
+            .ok_or(RulesetError::MissingDeck)?;
+        if let Some(&card) = deck
+            .iter()
+            .enumerate()
+            .find_map(|(i, card)| deck[..i].contains(card).then_some(card))
+        {
+            return Err(RulesetError::RepeatedCard { card });
+        }
+        if let Some(&card) = wild.iter().find(|card| !deck.contains(card)) {
+            return Err(RulesetError::UnknownWild { card });
+        }
+
+        let weakest = categories.first().ok_or(RulesetError::NoCategories)?;
+        if weakest.shape.len() > 1 || weakest.shape.iter().any(|&size| size > 1) {
+            return Err(RulesetError::WeakestNotUniversal {
+                name: weakest.name.clone(),
+            });
+        }
+
+        Ok(Ruleset {
+            name,
+            deck,
+            wild,
+            categories,
         })
+    }
+
+    fn value(&self, card: Card) -> u32 {
+        self.deck
+            .iter()
+            .position(|&c| c == card)
+            .unwrap_or_else(|| panic!("{} isn't in the {} deck", card, self.name)) as u32
+    }
+
+    // Wild cards always join the largest group of ordinary cards.
+    fn category(&self, cards: &[Card]) -> usize {
+        let mut card_counts = vec![0_u32; self.deck.len()];
+        let mut wild_count = 0;
+        for &card in cards {
+            match self.wild.contains(&card) {
+                true => wild_count += 1,
+                false => card_counts[self.value(card) as usize] += 1,
+            }
+        }
+
+        card_counts.sort_by_key(|&count| Reverse(count));
+        card_counts[0] += wild_count;
+
+        self.categories
+            .iter()
+            .rposition(|category| category.matches(&card_counts))
+            .unwrap_or(0)
+    }
+
+    // Cards outside the deck have no value, so hands are checked up front.
+    fn check(&self, cards: &[Card]) -> Result<(), RulesetError> {
+        match cards.iter().find(|card| !self.deck.contains(card)) {
+            Some(&card) => Err(RulesetError::NotInDeck { card }),
+            None => Ok(()),
+        }
+    }
+
+    // The card the wilds in `cards` stand in for: the largest ordinary group,
+    // the stronger one on a tie, or the best ordinary card if all are wild.
+    fn wild_target(&self, cards: &[Card]) -> Option<Card> {
+        if !cards.iter().any(|card| self.wild.contains(card)) {
+            return None;
+        }
+
+        self.deck
+            .iter()
+            .filter(|card| !self.wild.contains(card))
+            .max_by_key(|&card| cards.iter().filter(|&c| c == card).count())
+            .copied()
+    }
+}
+
+fn parse_cards(labels: &str, line: usize) -> Result<Vec<Card>, RulesetError> {
+    labels
+        .chars()
+        .map(|label| Card::from_label(label).ok_or(RulesetError::UnknownCard { line, card: label }))
         .collect()
 }
 
-const CARD_VALUES: &'static [u32; 13] = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
-const CARD_VALUES_JACKS_WILD: &'static [u32; 13] = &[1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 10, 11, 12];
+/// How strong a hand is. Compared field by field: the category first, then
+/// the card values in the order they were dealt.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Strength {
+    category: usize,
+    values: Vec<u32>,
+}
+
+fn hand_strength(cards: &[Card], rules: &Ruleset) -> Strength {
+    Strength {
+        category: rules.category(cards),
+        values: cards.iter().map(|&card| rules.value(card)).collect(),
+    }
+}
+
+fn rank_cards<'a>(hand: &'a Hand, rules: &Ruleset) -> (Strength, &'a Hand) {
+    (hand_strength(&hand.cards, rules), hand)
+}
 
-fn rank_cards(hand: &Hand, jacks_wild: bool) -> (u32, &Hand) {
-    let mut card_values = [0_u32; 5];
-    let mut card_counts = [0_u32; 13];
-    let mut tie_breaker = 0;
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Suit {
+    Clubs,
+    Diamonds,
+    Hearts,
+    Spades,
+}
 
-    for (i, card) in hand.cards.iter().enumerate() {
-        let value_index = match card {
-            'A' => 0xC,
-            'K' => 0xB,
-            'Q' => 0xA,
-            'J' => 0x9,
-            'T' => 0x8,
-            num => num.to_digit(10).unwrap() as usize - 0x2,
-        };
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SuitedCard {
+    value: Card,
+    suit: Suit,
+}
 
-        let value = if jacks_wild {
-            CARD_VALUES_JACKS_WILD[value_index]
-        } else {
-            CARD_VALUES[value_index]
+impl fmt::Display for SuitedCard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let suit = match self.suit {
+            Suit::Clubs => 'c',
+            Suit::Diamonds => 'd',
+            Suit::Hearts => 'h',
+            Suit::Spades => 's',
         };
+        write!(f, "{}{}", self.value, suit)
+    }
+}
+
+const POKER_CATEGORIES: [&str; 9] = [
+    "high card",
+    "one pair",
+    "two pair",
+    "three of a kind",
+    "straight",
+    "flush",
+    "full house",
+    "four of a kind",
+    "straight flush",
+];
+
+/// Poker hands, written as value-suit pairs like `AsKhTd9c2s`.
+struct PokerFormat;
+
+impl CardReader for PokerFormat {
+    type Card = SuitedCard;
+
+    fn hand_size(&self) -> usize {
+        HAND_SIZE
+    }
+
+    fn read(&self, cards: &str) -> Result<Vec<SuitedCard>, String> {
+        let chars: Vec<char> = cards.chars().collect();
+        chars
+            .chunks(2)
+            .map(|pair| {
+                let suit = match pair.get(1) {
+                    Some('c') => Some(Suit::Clubs),
+                    Some('d') => Some(Suit::Diamonds),
+                    Some('h') => Some(Suit::Hearts),
+                    Some('s') => Some(Suit::Spades),
+                    _ => None,
+                };
+                match (Card::from_label(pair[0]), suit) {
+                    (Some(value), Some(suit)) => Ok(SuitedCard { value, suit }),
+                    _ => Err(pair.iter().collect()),
+                }
+            })
+            .collect()
+    }
+
+    // There's only one of each card in a poker deck.
+    fn repeated(&self, cards: &[SuitedCard]) -> Option<String> {
+        cards
+            .iter()
+            .enumerate()
+            .find(|&(i, card)| cards[..i].contains(card))
+            .map(|(_, card)| card.to_string())
+    }
+}
 
-        card_values[i] = value;
-        card_counts[value as usize] += 1;
-        tie_breaker += value * 16_u32.pow(4 - i as u32);
+// The values in a poker `Strength` are one per group, biggest group first and
+// then highest value first, so kickers only count once the groups tie. A
+// straight is valued by its top card alone, which is the 5 for an ace-low one.
+// Values run from 2 up to 14 for the ace.
+fn rank_poker(hand: &Hand<SuitedCard>) -> (Strength, &Hand<SuitedCard>) {
+    let cards = &hand.cards;
+
+    let mut groups: Vec<(u32, u32)> = Vec::new();
+    for card in cards {
+        let value = card.value as u32 + 2;
+        match groups.iter_mut().find(|(_, v)| *v == value) {
+            Some((count, _)) => *count += 1,
+            None => groups.push((1, value)),
+        }
     }
+    groups.sort_by(|a, b| b.cmp(a));
+    let mut values: Vec<u32> = groups.iter().map(|&(_, value)| value).collect();
 
-    let wild_count = if jacks_wild {
-        let jacks_count = card_counts[0];
-        card_counts[0] = 0;
-        jacks_count
-    } else {
-        0
+    let flush = cards.iter().all(|card| card.suit == cards[0].suit);
+    let straight_top = match values[..] {
+        [14, 5, 4, 3, 2] => Some(5),
+        [top, .., low] if values.len() == 5 && top - low == 4 => Some(top),
+        _ => None,
     };
 
-    card_counts.sort_by(|&a, &b| b.cmp(&a));
-    card_counts[0] += wild_count;
-
-    let rank = match &card_counts[0..2] {
-        [5, ..] => 0x7,
-        [4, ..] => 0x6,
-        [3, 2] => 0x5,
-        [3, ..] => 0x4,
-        [2, 2] => 0x3,
-        [2, ..] => 0x2,
-        _ => 0x1,
+    let counts: Vec<u32> = groups.iter().map(|&(count, _)| count).collect();
+    let category = match (straight_top, flush, &counts[..]) {
+        (Some(_), true, _) => 8,
+        (_, _, [4, ..]) => 7,
+        (_, _, [3, 2]) => 6,
+        (_, true, _) => 5,
+        (Some(_), _, _) => 4,
+        (_, _, [3, ..]) => 3,
+        (_, _, [2, 2, ..]) => 2,
+        (_, _, [2, ..]) => 1,
+        _ => 0,
     };
+    if let Some(top) = straight_top {
+        values = vec![top];
+    }
 
-    (rank * 16_u32.pow(5) + tie_breaker, hand)
+    (Strength { category, values }, hand)
 }
 
-fn calculate_score(ranked_hands: &mut [(u32, &Hand)]) -> u32 {
+fn calculate_score<C>(ranked_hands: &mut [(Strength, &Hand<C>)]) -> u32 {
     ranked_hands.sort_by(|a, b| a.0.cmp(&b.0));
 
     ranked_hands
@@ -80,23 +612,411 @@ fn calculate_score(ranked_hands: &mut [(u32, &Hand)]) -> u32 {
         .sum()
 }
 
-fn part1(hands: &[Hand]) -> u32 {
-    let mut ranked_hands: Vec<(u32, &Hand)> =
-        hands.iter().map(|hand| rank_cards(hand, false)).collect();
+fn winnings(hands: &[Hand], rules: &Ruleset) -> u32 {
+    let mut ranked_hands: Vec<(Strength, &Hand)> =
+        hands.iter().map(|hand| rank_cards(hand, rules)).collect();
 
     calculate_score(&mut ranked_hands)
 }
 
-fn part2(hands: &[Hand]) -> u32 {
-    let mut ranked_hands: Vec<(u32, &Hand)> =
-        hands.iter().map(|hand| rank_cards(hand, true)).collect();
+fn poker_winnings(hands: &[Hand<SuitedCard>]) -> u32 {
+    let mut ranked_hands: Vec<(Strength, &Hand<SuitedCard>)> =
+        hands.iter().map(rank_poker).collect();
 
     calculate_score(&mut ranked_hands)
 }
 
+/// Why one hand ended up where it did.
+struct Explanation<'a> {
+    hand: &'a Hand,
+    category: &'a str,
+    wild_target: Option<Card>,
+    rank: usize,
+    winnings: u32,
+}
+
+// Returned weakest hand first, which is also rank order.
+fn explain<'a>(hands: &'a [Hand], rules: &'a Ruleset) -> Vec<Explanation<'a>> {
+    let mut ranked: Vec<(Strength, usize)> = hands
+        .iter()
+        .enumerate()
+        .map(|(i, hand)| (rank_cards(hand, rules).0, i))
+        .collect();
+    ranked.sort();
+
+    ranked
+        .into_iter()
+        .enumerate()
+        .map(|(position, (strength, i))| Explanation {
+            hand: &hands[i],
+            category: &rules.categories[strength.category].name,
+            wild_target: rules.wild_target(&hands[i].cards),
+            rank: position + 1,
+            winnings: hands[i].bid * (position + 1) as u32,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortKey {
+    Rank,
+    Input,
+    Bid,
+    Winnings,
+}
+
+fn parse_sort_key(arg: &str) -> SortKey {
+    match arg {
+        "rank" => SortKey::Rank,
+        "input" => SortKey::Input,
+        "bid" => SortKey::Bid,
+        "winnings" => SortKey::Winnings,
+        other => panic!("Unexpected sort key: {}", other),
+    }
+}
+
+// Ties keep rank order, since the sort is stable.
+fn sort_explanations(explanations: &mut [Explanation], key: SortKey) {
+    match key {
+        SortKey::Rank => explanations.sort_by_key(|e| e.rank),
+        SortKey::Input => explanations.sort_by_key(|e| e.hand.line),
+        SortKey::Bid => explanations.sort_by_key(|e| Reverse(e.hand.bid)),
+        SortKey::Winnings => explanations.sort_by_key(|e| Reverse(e.winnings)),
+    }
+}
+
+fn format_explanations(explanations: &[Explanation]) -> String {
+    let mut out = format!(
+        "{:>5} {:>5}  {:<12} {:<16} {:>5} {:>6} {:>8}\n",
+        "rank", "line", "hand", "type", "wild", "bid", "winnings"
+    );
+    for e in explanations {
+        let cards = e.hand.label();
+        let wild = e
+            .wild_target
+            .map_or("-".to_string(), |card| format!("={}", card));
+        out += &format!(
+            "{:>5} {:>5}  {:<12} {:<16} {:>5} {:>6} {:>8}\n",
+            e.rank, e.hand.line, cards, e.category, wild, e.hand.bid, e.winnings
+        );
+    }
+    out
+}
+
+// Ruleset category names are free text, so they may need quoting.
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+fn explanations_to_csv(explanations: &[Explanation]) -> String {
+    let mut out = String::from("rank,line,hand,type,wild_as,bid,winnings\n");
+    for e in explanations {
+        let wild = e.wild_target.map_or(String::new(), |card| card.to_string());
+        out += &format!(
+            "{},{},{},{},{},{},{}\n",
+            e.rank,
+            e.hand.line,
+            e.hand.label(),
+            csv_field(e.category),
+            wild,
+            e.hand.bid,
+            e.winnings
+        );
+    }
+    out
+}
+
+// A xorshift generator, so simulations are reproducible from a seed without
+// pulling in a crate.
+struct Rng(u64);
+
+impl Rng {
+    // Xorshift never leaves zero, so that seed is nudged.
+    fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// What a simulated tournament turned up.
+struct Simulation {
+    rounds: usize,
+    // How many dealt opponent hands fell in each of the ruleset's categories.
+    type_counts: Vec<usize>,
+    // Summed over the rounds, where rank 1 is the weakest hand at the table.
+    rank_total: usize,
+}
+
+impl Simulation {
+    fn expected_rank(&self) -> f64 {
+        self.rank_total as f64 / self.rounds as f64
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum SimulationError {
+    EmptyHand,
+    NoRounds,
+    ShortOf { card: Card },
+    TooManyOpponents { opponents: usize },
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimulationError::EmptyHand => write!(f, "can't simulate an empty hand"),
+            SimulationError::NoRounds => write!(f, "can't simulate 0 rounds"),
+            SimulationError::ShortOf { card } => {
+                write!(f, "not enough {}s in the shoe", card)
+            }
+            SimulationError::TooManyOpponents { opponents } => {
+                write!(f, "not enough cards for {} opponents", opponents)
+            }
+        }
+    }
+}
+
+// Each round deals `opponents` hands from a shoe holding `copies` of every
+// card in the deck, less the cards in `hand`. Ties go in the hand's favour.
+fn simulate(
+    rules: &Ruleset,
+    hand: &[Card],
+    opponents: usize,
+    rounds: usize,
+    copies: usize,
+    rng: &mut Rng,
+) -> Result<Simulation, SimulationError> {
+    if hand.is_empty() {
+        return Err(SimulationError::EmptyHand);
+    }
+    if rounds == 0 {
+        return Err(SimulationError::NoRounds);
+    }
+
+    let mut shoe: Vec<Card> = rules
+        .deck
+        .iter()
+        .flat_map(|&card| vec![card; copies])
+        .collect();
+    for card in hand {
+        match shoe.iter().position(|c| c == card) {
+            Some(i) => shoe.swap_remove(i),
+            None => return Err(SimulationError::ShortOf { card: *card }),
+        };
+    }
+
+    let dealt = opponents
+        .checked_mul(hand.len())
+        .filter(|&dealt| dealt <= shoe.len())
+        .ok_or(SimulationError::TooManyOpponents { opponents })?;
+
+    let strength = hand_strength(hand, rules);
+    let mut simulation = Simulation {
+        rounds,
+        type_counts: vec![0; rules.categories.len()],
+        rank_total: 0,
+    };
+
+    for _ in 0..rounds {
+        // A partial Fisher-Yates shuffle, dealing from the front of the shoe.
+        for i in 0..dealt {
+            let j = i + rng.below(shoe.len() - i);
+            shoe.swap(i, j);
+        }
+
+        let mut rank = 1;
+        for cards in shoe[..dealt].chunks(hand.len()) {
+            let theirs = hand_strength(cards, rules);
+            simulation.type_counts[theirs.category] += 1;
+            if theirs <= strength {
+                rank += 1;
+            }
+        }
+        simulation.rank_total += rank;
+    }
+
+    Ok(simulation)
+}
+
+fn format_simulation(simulation: &Simulation, rules: &Ruleset, opponents: usize) -> String {
+    let mut out = format!(
+        "expected rank: {:.2} of {}\n",
+        simulation.expected_rank(),
+        opponents + 1
+    );
+
+    let dealt: usize = simulation.type_counts.iter().sum();
+    for (category, &count) in rules.categories.iter().zip(&simulation.type_counts).rev() {
+        out += &format!(
+            "  {:<16} {:>8} {:>7.3}%\n",
+            category.name,
+            count,
+            100.0 * count as f64 / dealt.max(1) as f64
+        );
+    }
+    out
+}
+
+fn part1(hands: &[Hand]) -> u32 {
+    winnings(hands, &Ruleset::standard())
+}
+
+fn part2(hands: &[Hand]) -> u32 {
+    winnings(hands, &Ruleset::jokers())
+}
+
+// Either a built-in ruleset by name or a path to a ruleset file.
+fn load_rules(arg: &str) -> Ruleset {
+    match arg {
+        "standard" => Ruleset::standard(),
+        "jokers" => Ruleset::jokers(),
+        path => match Ruleset::parse(&fs::read_to_string(path).unwrap()) {
+            Ok(rules) => rules,
+            Err(err) => {
+                eprintln!("Invalid ruleset {}: {}", path, err);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+fn read_hands<R: CardReader>(input: &str, reader: &R) -> Vec<Hand<R::Card>> {
+    match parse_input(input, reader) {
+        Ok(hands) => hands,
+        Err(err) => {
+            eprintln!("Invalid hands: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn check_hands(hands: &[Hand], rules: &Ruleset) {
+    for hand in hands {
+        if let Err(err) = rules.check(&hand.cards) {
+            eprintln!("Invalid hand on line {}: {}", hand.line, err);
+            process::exit(1);
+        }
+    }
+}
+
 fn main() {
     let input = include_str!("../input.txt");
-    let hands = parse_input(&input);
+
+    if env::args().nth(1).as_deref() == Some("poker") {
+        let path = env::args().nth(2).expect("usage: poker <hands file>");
+        let hands = read_hands(&fs::read_to_string(path).unwrap(), &PokerFormat);
+        for hand in &hands {
+            let (strength, _) = rank_poker(hand);
+            println!("{} {}", hand.label(), POKER_CATEGORIES[strength.category]);
+        }
+        println!("Poker winnings: {}", poker_winnings(&hands));
+        return;
+    }
+
+    let mut format = HandFormat::new(HAND_SIZE);
+    for arg in env::args().skip(1) {
+        if let Some(size) = arg.strip_prefix("--hand-size=") {
+            format.size = size.parse().unwrap();
+        }
+        if let Some(alias) = arg.strip_prefix("--alias=") {
+            format.aliases.push(parse_alias(alias));
+        }
+    }
+    let rules = env::args()
+        .skip(1)
+        .find_map(|arg| arg.strip_prefix("--rules=").map(load_rules));
+
+    if env::args().nth(1).as_deref() == Some("simulate") {
+        let rules = rules.unwrap_or_else(Ruleset::standard);
+        let cards = env::args()
+            .skip(2)
+            .find(|arg| !arg.starts_with("--"))
+            .expect("usage: simulate <hand>");
+        let hand = match format.read(&cards) {
+            Ok(hand) => hand,
+            Err(card) => {
+                eprintln!("Invalid hand: {:?} is not a card", card);
+                process::exit(1);
+            }
+        };
+        if hand.len() != format.size {
+            eprintln!(
+                "Invalid hand: {} cards instead of {}",
+                hand.len(),
+                format.size
+            );
+            process::exit(1);
+        }
+        if let Err(err) = rules.check(&hand) {
+            eprintln!("Invalid hand: {}", err);
+            process::exit(1);
+        }
+
+        let flag = |name: &str, default: usize| {
+            env::args()
+                .skip(1)
+                .find_map(|arg| Some(arg.strip_prefix(name)?.parse().unwrap()))
+                .unwrap_or(default)
+        };
+        let opponents = flag("--opponents=", 9);
+        let rounds = flag("--rounds=", 10_000);
+        let copies = flag("--copies=", 4);
+        let seed = flag("--seed=", 2023);
+
+        let mut rng = Rng::new(seed as u64);
+        let simulation = match simulate(&rules, &hand, opponents, rounds, copies, &mut rng) {
+            Ok(simulation) => simulation,
+            Err(err) => {
+                eprintln!("Invalid simulation: {}", err);
+                process::exit(1);
+            }
+        };
+        println!(
+            "{} against {} opponents, {} rounds under {} (seed {})",
+            cards, opponents, rounds, rules.name, seed
+        );
+        print!("{}", format_simulation(&simulation, &rules, opponents));
+        return;
+    }
+
+    let hands = read_hands(input, &format);
+
+    if env::args().nth(1).as_deref() == Some("explain") {
+        let rules = rules.unwrap_or_else(Ruleset::jokers);
+        check_hands(&hands, &rules);
+        let mut explanations = explain(&hands, &rules);
+        if let Some(key) = env::args()
+            .skip(1)
+            .find_map(|arg| arg.strip_prefix("--sort=").map(parse_sort_key))
+        {
+            sort_explanations(&mut explanations, key);
+        }
+
+        match env::args().any(|arg| arg == "--csv") {
+            true => print!("{}", explanations_to_csv(&explanations)),
+            false => print!("{}", format_explanations(&explanations)),
+        }
+        return;
+    }
+
+    if let Some(rules) = rules {
+        check_hands(&hands, &rules);
+        println!(
+            "Winnings under {}: {}",
+            rules.name,
+            winnings(&hands, &rules)
+        );
+        return;
+    }
 
     let part1_result = part1(&hands);
     println!("Part 1: {}", part1_result);
@@ -109,7 +1029,11 @@ fn main() {
 mod tests {
     use super::*;
 
-    const SAMPLE_DATA: &'static str = r"
+    fn cards(labels: &str) -> Vec<Card> {
+        HandFormat::new(0).read(labels).unwrap()
+    }
+
+    const SAMPLE_DATA: &str = r"
     32T3K 765
     T55J5 684
     KK677 28
@@ -119,15 +1043,295 @@ mod tests {
 
     #[test]
     fn it_should_find_the_winnings_when_jacks_are_not_wild() {
-        let input = parse_input(&SAMPLE_DATA);
+        let input = parse_input(SAMPLE_DATA, &HandFormat::new(HAND_SIZE)).unwrap();
         let result = super::part1(&input);
         assert_eq!(result, 6440);
     }
 
     #[test]
     fn it_should_find_the_winnings_when_jacks_are_wild() {
-        let input = parse_input(&SAMPLE_DATA);
+        let input = parse_input(SAMPLE_DATA, &HandFormat::new(HAND_SIZE)).unwrap();
         let result = super::part2(&input);
         assert_eq!(result, 5905);
     }
+
+    #[test]
+    fn it_should_reject_invalid_hands() {
+        let format = HandFormat::new(HAND_SIZE);
+
+        assert_eq!(
+            parse_input("32T3K 765\n32T1K 1", &format).err(),
+            Some(HandError::InvalidCard {
+                line: 2,
+                card: "1".to_string()
+            })
+        );
+        assert_eq!(
+            parse_input("\nkk677 28", &format).err(),
+            Some(HandError::InvalidCard {
+                line: 2,
+                card: "k".to_string()
+            })
+        );
+        assert_eq!(
+            parse_input("KK6777 28", &format).err(),
+            Some(HandError::WrongLength {
+                line: 1,
+                found: 6,
+                expected: 5
+            })
+        );
+        assert_eq!(
+            parse_input("KK677", &format).err(),
+            Some(HandError::MissingBid { line: 1 })
+        );
+        assert_eq!(
+            parse_input("KK677 x", &format).err(),
+            Some(HandError::BadBid {
+                line: 1,
+                bid: "x".to_string()
+            })
+        );
+        assert_eq!(
+            parse_input("Ah2h3h4h5 1", &PokerFormat).err(),
+            Some(HandError::InvalidCard {
+                line: 1,
+                card: "5".to_string()
+            })
+        );
+        assert_eq!(
+            parse_input("AcAdAhAsAc 1", &PokerFormat).err(),
+            Some(HandError::RepeatedCard {
+                line: 1,
+                card: "Ac".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn it_should_read_aliases() {
+        let mut format = HandFormat::new(HAND_SIZE);
+        format.aliases.push(parse_alias("10=T"));
+        format.aliases.push(parse_alias("1=A"));
+
+        let hands = parse_input("10 10 1 9 2 5", &format);
+        assert!(hands.is_err());
+        let hands = parse_input("1010192 5", &format).unwrap();
+        assert_eq!(hands[0].label(), "TTA92");
+    }
+
+    #[test]
+    fn it_should_rank_hands_of_any_size() {
+        let hands = parse_input(
+            "
+            AAAAKKKQ 1
+            AAAAKKKK 2
+            23456789 3
+            AAAAAAA2 4
+            ",
+            &HandFormat::new(8),
+        )
+        .unwrap();
+        let rules = Ruleset::standard();
+
+        // Seven of a kind is past the top category, so it counts as five.
+        let (seven, _) = rank_cards(&hands[3], &rules);
+        assert_eq!(seven.category, 6);
+        assert_eq!(seven.values.len(), 8);
+        assert!(rank_cards(&hands[0], &rules).0 < rank_cards(&hands[1], &rules).0);
+
+        assert_eq!(winnings(&hands, &rules), 3 + 2 + 3 * 2 + 4 * 4);
+    }
+
+    #[test]
+    fn it_should_rank_poker_hands() {
+        let category = |cards: &str| {
+            let hand = &parse_input(&format!("{} 1", cards), &PokerFormat).unwrap()[0];
+            POKER_CATEGORIES[rank_poker(hand).0.category]
+        };
+
+        assert_eq!(category("9hThJhQhKh"), "straight flush");
+        assert_eq!(category("Ah2h3h4h5h"), "straight flush");
+        assert_eq!(category("9c9d9h9s2c"), "four of a kind");
+        assert_eq!(category("9c9d9h2s2c"), "full house");
+        assert_eq!(category("2h7h9hJhKh"), "flush");
+        assert_eq!(category("Ac2d3h4s5c"), "straight");
+        assert_eq!(category("9c9d9h3s2c"), "three of a kind");
+        assert_eq!(category("9c9d3h3s2c"), "two pair");
+        assert_eq!(category("9c9d4h3s2c"), "one pair");
+        assert_eq!(category("Qc2d3h4s5c"), "high card");
+        assert_eq!(category("QcKdAh2s3c"), "high card");
+    }
+
+    #[test]
+    fn it_should_break_poker_ties_by_kickers() {
+        let hands = parse_input(
+            "
+            Ac2d3h4s5c 1
+            2c3d4h5s6c 2
+            KcKdAh3s2c 3
+            KcKdQh3s2c 4
+            4c4d4h2s2c 5
+            3c3d3hAsAc 6
+            ",
+            &PokerFormat,
+        )
+        .unwrap();
+        let strengths: Vec<Strength> = hands.iter().map(|hand| rank_poker(hand).0).collect();
+
+        assert!(strengths[0] < strengths[1]);
+        assert!(strengths[3] < strengths[2]);
+        assert!(strengths[5] < strengths[4]);
+        assert_eq!(poker_winnings(&hands), 81);
+    }
+
+    #[test]
+    fn it_should_explain_each_hand() {
+        let hands = parse_input(SAMPLE_DATA, &HandFormat::new(HAND_SIZE)).unwrap();
+        let rules = Ruleset::jokers();
+        let mut explanations = explain(&hands, &rules);
+
+        let top = &explanations[4];
+        assert_eq!(top.hand.cards, cards("KTJJT"));
+        assert_eq!(top.category, "four of a kind");
+        assert_eq!(top.wild_target, Some(Card::Ten));
+        assert_eq!((top.rank, top.winnings), (5, 1100));
+        assert_eq!(explanations[0].wild_target, None);
+        assert_eq!(
+            explanations.iter().map(|e| e.winnings).sum::<u32>(),
+            part2(&hands)
+        );
+
+        sort_explanations(&mut explanations, SortKey::Input);
+        assert_eq!(
+            explanations_to_csv(&explanations).lines().nth(2),
+            Some("3,3,T55J5,four of a kind,5,684,2052")
+        );
+        sort_explanations(&mut explanations, SortKey::Winnings);
+        assert_eq!(explanations[0].rank, 3);
+
+        let rules = Ruleset::parse("deck: 23456789TJQKA\ncategory: \"big\", \"small\" =").unwrap();
+        let csv = explanations_to_csv(&explain(&hands, &rules));
+        assert!(csv.ends_with(",\"\"\"big\"\", \"\"small\"\"\",,28,140\n"));
+    }
+
+    #[test]
+    fn it_should_assign_all_wild_hands_to_the_best_card() {
+        let jokers = Ruleset::jokers();
+        assert_eq!(jokers.wild_target(&cards("JJJJJ")), Some(Card::Ace));
+        assert_eq!(jokers.wild_target(&cards("22AAJ")), Some(Card::Ace));
+        assert_eq!(Ruleset::standard().wild_target(&cards("JJJJJ")), None);
+    }
+
+    #[test]
+    fn it_should_simulate_tournaments_reproducibly() {
+        let rules = Ruleset::standard();
+        let run = |hand: &str, copies, seed| {
+            simulate(&rules, &cards(hand), 9, 500, copies, &mut Rng::new(seed)).unwrap()
+        };
+
+        let first = run("KK677", 4, 7);
+        let again = run("KK677", 4, 7);
+        assert_eq!(first.rank_total, again.rank_total);
+        assert_eq!(first.type_counts, again.type_counts);
+        assert_eq!(first.type_counts.iter().sum::<usize>(), 9 * 500);
+        assert!(first.type_counts[0] > first.type_counts[6]);
+
+        // Nothing beats or ties five aces when the shoe has no aces left.
+        assert_eq!(run("AAAAA", 5, 7).expected_rank(), 10.0);
+        assert!(run("23457", 4, 7).expected_rank() < 1.5);
+    }
+
+    #[test]
+    fn it_should_reject_impossible_simulations() {
+        let rules = Ruleset::standard();
+        let run = |hand: &str, opponents, rounds, copies| {
+            simulate(
+                &rules,
+                &cards(hand),
+                opponents,
+                rounds,
+                copies,
+                &mut Rng::new(7),
+            )
+            .err()
+        };
+
+        assert_eq!(
+            run("AAAAA", 9, 10, 2),
+            Some(SimulationError::ShortOf { card: Card::Ace })
+        );
+        assert_eq!(
+            run("KK677", 100, 10, 4),
+            Some(SimulationError::TooManyOpponents { opponents: 100 })
+        );
+        assert_eq!(run("KK677", 9, 0, 4), Some(SimulationError::NoRounds));
+        assert_eq!(run("", 9, 10, 4), Some(SimulationError::EmptyHand));
+        assert_eq!(run("KK677", 9, 10, 4), None);
+    }
+
+    #[test]
+    fn it_should_rank_with_a_custom_ruleset() {
+        // Twos are wild and every hand is either a pair or nothing.
+        let rules = Ruleset::parse(
+            "name: deuces\n\
+             deck: 23456789TJQKA\n\
+             wild: 2\n\
+             category: nothing =\n\
+             category: pair = 2\n",
+        )
+        .unwrap();
+
+        assert_eq!(rules.category(&cards("34567")), 0);
+        assert_eq!(rules.category(&cards("24567")), 1);
+        assert_eq!(rules.category(&cards("AAAAA")), 1);
+        assert_eq!(Ruleset::jokers().category(&cards("JJJJJ")), 6);
+        assert_eq!(Ruleset::standard().category(&cards("23323")), 4);
+    }
+
+    #[test]
+    fn it_should_reject_broken_rulesets() {
+        assert_eq!(
+            Ruleset::parse("category: x = 1"),
+            Err(RulesetError::MissingDeck)
+        );
+        assert_eq!(
+            Ruleset::parse("deck: 234\nwild: J\ncategory: x = 1"),
+            Err(RulesetError::UnknownWild { card: Card::Jack })
+        );
+        assert_eq!(
+            Ruleset::parse("deck: 2342"),
+            Err(RulesetError::RepeatedCard { card: Card::Two })
+        );
+        assert_eq!(
+            Ruleset::parse("deck: 234\ncategory: pair = 2"),
+            Err(RulesetError::WeakestNotUniversal {
+                name: "pair".to_string()
+            })
+        );
+        assert_eq!(
+            Ruleset::parse("deck: 234\ncategory: x = 1 1 1 1 1 1"),
+            Err(RulesetError::WeakestNotUniversal {
+                name: "x".to_string()
+            })
+        );
+        assert_eq!(
+            Ruleset::parse("deck:\ncategory: x = 1"),
+            Err(RulesetError::MissingDeck)
+        );
+
+        let no_jacks = Ruleset::parse("deck: 23456789TQKA\ncategory: x =").unwrap();
+        assert_eq!(
+            no_jacks.check(&cards("KTJJT")),
+            Err(RulesetError::NotInDeck { card: Card::Jack })
+        );
+        assert_eq!(no_jacks.check(&cards("KTQQT")), Ok(()));
+        assert_eq!(
+            Ruleset::parse("deck: 234\nsuits: 4"),
+            Err(RulesetError::UnknownKey {
+                line: 2,
+                key: "suits".to_string()
+            })
+        );
+    }
 }